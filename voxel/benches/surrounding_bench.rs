@@ -9,6 +9,7 @@ use std::io::Read;
 
 use voxel::chunk::{Chunk, BoxedChunk, CHUNK_HEIGHT, CHUNK_WIDTH, CHUNK_LENGTH, LocalBlockPosition};
 use voxel::block::{Block, BlockRegistry, BlockRegistryFile};
+use voxel::mesh::{ChunkMesh, MeshMode};
 
 use criterion::Criterion;
 use criterion::black_box;
@@ -55,11 +56,26 @@ fn surrounding(c: &mut Criterion) {
         //}
     //}));
     
-    let chunk = BoxedChunk::flat(Block::hard_create(16), 5);
+    let chunk = BoxedChunk::flat(Block::hard_create(1), 5);
     let (registry, failures) = BlockRegistry::from_file("resources/registry.json").unwrap();
+    let mask_chunk = BoxedChunk::flat(Block::hard_create(1), 5);
+    let mask_registry = registry.clone();
     c.bench_function("visible_blocks", move |b| b.iter(|| {
         chunk.visible_blocks(&registry);
     }));
+
+    c.bench_function("visible_mask", move |b| b.iter(|| {
+        black_box(mask_chunk.visible_mask(&mask_registry));
+    }));
+}
+
+fn mesh(c: &mut Criterion) {
+    let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+    let chunk = BoxedChunk::flat(Block::hard_create(1), 5);
+
+    c.bench_function_over_inputs("mesh_flat", move |b, mode| b.iter(|| {
+        black_box(ChunkMesh::build(&chunk, &registry, *mode));
+    }), vec![MeshMode::Culled, MeshMode::Greedy]);
 }
 
 fn registry(c: &mut Criterion) {
@@ -79,5 +95,5 @@ fn registry(c: &mut Criterion) {
     //}));
 }
 
-criterion_group!(benches, surrounding, registry);
+criterion_group!(benches, surrounding, mesh, registry);
 criterion_main!(benches);
@@ -1,19 +1,53 @@
 
+// Width of block ids, picked with the `block-u8` and `block-u32` features and `u16` otherwise.
+#[cfg(all(feature = "block-u8", feature = "block-u32"))]
+compile_error!("only one of the `block-u8` and `block-u32` features can be enabled");
+
+#[cfg(feature = "block-u8")]
+pub type BlockSize = u8;
+#[cfg(all(feature = "block-u32", not(feature = "block-u8")))]
+pub type BlockSize = u32;
+#[cfg(not(any(feature = "block-u8", feature = "block-u32")))]
 pub type BlockSize = u16;
-pub const MAX_BLOCK_ID: usize = 2 << (::std::mem::size_of::<BlockSize>() * 8 - 1);
+
+/// Largest valid block id.
+pub const MAX_BLOCK_ID: usize = BlockSize::MAX as usize;
+
+/// Index into the combinations of a block's declared states, see `BlockDeclaration::states`.
+pub type BlockState = u16;
+pub const MAX_BLOCK_STATES: usize = BlockState::MAX as usize + 1;
 
 pub const EMPTY_BLOCK: Block = Block::hard_create(0);
 
-#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct Block(BlockSize);
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Block {
+    id: BlockSize,
+    state: BlockState,
+}
 
 impl Block {
-    pub const fn hard_create(block: u16) -> Block {
-        Block(block)
+    pub const fn hard_create(block: BlockSize) -> Block {
+        Block::with_state(block, 0)
+    }
+
+    pub const fn with_state(block: BlockSize, state: BlockState) -> Block {
+        Block { id: block, state }
     }
 
-    pub fn id(&self) -> u16 {
-        self.0
+    pub fn id(&self) -> BlockSize {
+        self.id
+    }
+
+    pub fn state(&self) -> BlockState {
+        self.state
     }
 }
 
+/// Block with the given id, failing if it is larger than `MAX_BLOCK_ID`.
+impl std::convert::TryFrom<usize> for Block {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(id: usize) -> Result<Block, Self::Error> {
+        BlockSize::try_from(id).map(Block::hard_create)
+    }
+}
@@ -0,0 +1,270 @@
+//! Errors produced while loading block registries.
+//!
+//! A `RegistryError` means a file couldn't be loaded at all, while a `FailedDeclaration` rejects a
+//! single block and the rest of the file is still loaded.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::block::{BlockSize, BlockIdentifier, RegistryFormat};
+
+#[derive(Debug)]
+pub enum RegistryError {
+    IO {
+        path: Option<PathBuf>,
+        error: io::Error,
+    },
+    /// Malformed file, such as a syntax error, an unknown field or a value of the wrong type.
+    Parse {
+        path: Option<PathBuf>,
+        format: RegistryFormat,
+        /// Line and column of the error starting at 1, if the parser reported them.
+        position: Option<(usize, usize)>,
+        error: Box<dyn Error + Send + Sync>,
+    },
+    Serialize {
+        format: RegistryFormat,
+        error: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl RegistryError {
+    pub(crate) fn io(error: io::Error) -> RegistryError {
+        RegistryError::IO {
+            path: None,
+            error,
+        }
+    }
+
+    pub(crate) fn json(error: serde_json::Error) -> RegistryError {
+        // Errors that aren't about the contents are on line 0.
+        let position = Some((error.line(), error.column())).filter(|(line, _)| *line > 0);
+        RegistryError::parse(RegistryFormat::Json, position, error.into())
+    }
+
+    pub(crate) fn ron(error: ron::de::Error) -> RegistryError {
+        let position = match &error {
+            ron::de::Error::Parser(_, position) => Some((position.line, position.col)),
+            _ => None,
+        };
+        RegistryError::parse(RegistryFormat::Ron, position, error.into())
+    }
+
+    pub(crate) fn toml(error: toml::de::Error) -> RegistryError {
+        // Starts at 0 unlike the others.
+        let position = error.line_col().map(|(line, column)| (line + 1, column + 1));
+        RegistryError::parse(RegistryFormat::Toml, position, error.into())
+    }
+
+    fn parse(format: RegistryFormat, position: Option<(usize, usize)>, error: Box<dyn Error + Send + Sync>) -> RegistryError {
+        RegistryError::Parse {
+            path: None,
+            format,
+            position,
+            error,
+        }
+    }
+
+    /// The same error, happening in the file at `path`.
+    pub(crate) fn at(mut self, file: &Path) -> RegistryError {
+        match &mut self {
+            RegistryError::IO { path, .. } | RegistryError::Parse { path, .. } => *path = Some(file.to_owned()),
+            RegistryError::Serialize { .. } => {},
+        }
+        self
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            RegistryError::IO { path, .. } | RegistryError::Parse { path, .. } => path.as_deref(),
+            RegistryError::Serialize { .. } => None,
+        }
+    }
+
+    /// Line and column of a parse error.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            RegistryError::Parse { position, .. } => *position,
+            RegistryError::IO { .. } | RegistryError::Serialize { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = self.path() {
+            write!(f, "{}: ", path.display())?;
+        }
+
+        match self {
+            RegistryError::IO { error, .. } => write!(f, "{}", error),
+            // Parser messages already include the position.
+            RegistryError::Parse { format, error, .. } => write!(f, "invalid {}: {}", format, error),
+            RegistryError::Serialize { format, error } => write!(f, "failed to write {}: {}", format, error),
+        }
+    }
+}
+
+impl Error for RegistryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RegistryError::IO { error, .. } => Some(error),
+            RegistryError::Parse { error, .. } | RegistryError::Serialize { error, .. } => Some(error.as_ref()),
+        }
+    }
+}
+
+/// The earlier declaration a failed declaration clashed with.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Conflict {
+    pub id: BlockSize,
+    /// File the earlier declaration came from, if it was loaded from one.
+    pub source: Option<PathBuf>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {}", self.id)?;
+        if let Some(source) = self.source.as_ref() {
+            write!(f, " in {}", source.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a declaration was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclarationError {
+    IdOutOfRange {
+        max: usize,
+    },
+    /// The id was already declared by an earlier file that can't be overridden.
+    DuplicateId(Conflict),
+    /// Another block already uses the identifier.
+    DuplicateIdentifier {
+        identifier: BlockIdentifier,
+        conflict: Conflict,
+    },
+    InvalidIdentifier {
+        identifier: String,
+        reason: String,
+    },
+    EmptyState {
+        state: String,
+    },
+    DuplicateState {
+        state: String,
+    },
+    DuplicateStateValue {
+        state: String,
+        value: String,
+    },
+    TooManyStates {
+        count: usize,
+        max: usize,
+    },
+}
+
+impl DeclarationError {
+    pub fn conflict(&self) -> Option<&Conflict> {
+        match self {
+            DeclarationError::DuplicateId(conflict) => Some(conflict),
+            DeclarationError::DuplicateIdentifier { conflict, .. } => Some(conflict),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DeclarationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeclarationError::IdOutOfRange { max } => write!(f, "id is larger than max block type {}", max),
+            DeclarationError::DuplicateId(conflict) => write!(f, "id is already declared by {}", conflict),
+            DeclarationError::DuplicateIdentifier { identifier, conflict } => {
+                write!(f, "identifier {} is already used by {}", identifier, conflict)
+            },
+            DeclarationError::InvalidIdentifier { identifier, reason } => {
+                write!(f, "invalid identifier {:?}: {}", identifier, reason)
+            },
+            DeclarationError::EmptyState { state } => write!(f, "state {:?} has no values", state),
+            DeclarationError::DuplicateState { state } => write!(f, "state {:?} is declared more than once", state),
+            DeclarationError::DuplicateStateValue { state, value } => {
+                write!(f, "state {:?} has duplicate value {:?}", state, value)
+            },
+            DeclarationError::TooManyStates { count, max } => {
+                write!(f, "{} state combinations is more than the max of {}", count, max)
+            },
+        }
+    }
+}
+
+impl Error for DeclarationError {}
+
+/// A block declaration that was rejected while the rest of its file was loaded.
+#[derive(Debug, Clone)]
+pub struct FailedDeclaration {
+    id: usize,
+    subtype: Option<usize>,
+    error: DeclarationError,
+    source: Option<PathBuf>,
+}
+
+impl FailedDeclaration {
+    pub(crate) fn new(id: usize, subtype: Option<usize>, error: DeclarationError) -> FailedDeclaration {
+        FailedDeclaration {
+            id,
+            subtype,
+            error,
+            source: None,
+        }
+    }
+
+    pub(crate) fn set_source(&mut self, source: Option<&Path>) {
+        self.source = source.map(|source| source.to_owned());
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Index of the declared state the failure is about, if any.
+    pub fn subtype(&self) -> Option<usize> {
+        self.subtype
+    }
+
+    pub fn error(&self) -> &DeclarationError {
+        &self.error
+    }
+
+    /// File containing the failed declaration, if it was loaded from one.
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    pub fn conflict(&self) -> Option<&Conflict> {
+        self.error.conflict()
+    }
+}
+
+impl fmt::Display for FailedDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(source) = self.source.as_ref() {
+            write!(f, "{}: ", source.display())?;
+        }
+
+        write!(f, "block {}", self.id)?;
+        if let Some(subtype) = self.subtype {
+            write!(f, " state {}", subtype)?;
+        }
+
+        write!(f, ": {}", self.error)
+    }
+}
+
+impl Error for FailedDeclaration {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
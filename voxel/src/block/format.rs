@@ -0,0 +1,199 @@
+//! File formats registries can be read from and written to.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::block::RegistryError;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RegistryFormat {
+    Json,
+    /// Optional values have to be written as `Some(..)` unless the file starts with
+    /// `#![enable(implicit_some)]`.
+    Ron,
+    Toml,
+}
+
+impl RegistryFormat {
+    pub const ALL: [RegistryFormat; 3] = [RegistryFormat::Json, RegistryFormat::Ron, RegistryFormat::Toml];
+
+    /// Format of a file going by its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<RegistryFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        RegistryFormat::ALL
+            .iter()
+            .find(|format| format.extension() == extension)
+            .cloned()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RegistryFormat::Json => "json",
+            RegistryFormat::Ron => "ron",
+            RegistryFormat::Toml => "toml",
+        }
+    }
+
+    pub(crate) fn deserialize<T: DeserializeOwned>(&self, contents: &str) -> Result<T, RegistryError> {
+        match self {
+            RegistryFormat::Json => serde_json::from_str(contents).map_err(RegistryError::json),
+            RegistryFormat::Ron => ron::de::from_str(contents).map_err(RegistryError::ron),
+            RegistryFormat::Toml => toml::from_str(contents).map_err(RegistryError::toml),
+        }
+    }
+
+    pub(crate) fn serialize<T: Serialize>(&self, value: &T) -> Result<String, RegistryError> {
+        let serialize_error = |error: Box<dyn std::error::Error + Send + Sync>| RegistryError::Serialize {
+            format: *self,
+            error,
+        };
+
+        match self {
+            RegistryFormat::Json => serde_json::to_string_pretty(value).map_err(|e| serialize_error(e.into())),
+            RegistryFormat::Ron => {
+                ron::ser::to_string_pretty(value, Default::default()).map_err(|e| serialize_error(e.into()))
+            },
+            RegistryFormat::Toml => toml::to_string(value).map_err(|e| serialize_error(e.into())),
+        }
+    }
+}
+
+/// JSON, which files without a known extension are read as.
+impl Default for RegistryFormat {
+    fn default() -> RegistryFormat {
+        RegistryFormat::Json
+    }
+}
+
+impl fmt::Display for RegistryFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryFormat::Json => write!(f, "JSON"),
+            RegistryFormat::Ron => write!(f, "RON"),
+            RegistryFormat::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::block::{BlockRegistry, BlockRegistryFile, RegistryFormat};
+
+    const RON: &str = r#"
+        // Comments are what the other formats are for.
+        #![enable(implicit_some)]
+        {
+            0: (
+                identifier: "core:air",
+                group: "Empty",
+                name: "Air",
+                color: (0, 0, 0),
+                transparency: 255,
+                collidable: 0,
+                hardness: 255,
+                friction: 0.6,
+            ),
+            "2": (
+                group: "Wood",
+                name: "Log",
+                color: [102, 76, 51],
+                transparency: 0,
+                states: [
+                    (name: "axis", values: ["x", "y", "z"]),
+                ],
+            ),
+        }
+    "#;
+
+    const TOML: &str = r#"
+        # Block ids have to be strings in TOML.
+        [0]
+        identifier = "core:air"
+        group = "Empty"
+        name = "Air"
+        color = [0, 0, 0]
+        transparency = 255
+        collidable = 0
+        hardness = 255
+
+        [2]
+        group = "Wood"
+        name = "Log"
+        color = [102, 76, 51]
+        transparency = 0
+
+        [[2.states]]
+        name = "axis"
+        values = ["x", "y", "z"]
+    "#;
+
+    const JSON: &str = r#"{
+        "0": { "identifier": "core:air", "group": "Empty", "name": "Air", "color": [0, 0, 0], "transparency": 255, "collidable": 0, "hardness": 255 },
+        "2": {
+            "group": "Wood",
+            "name": "Log",
+            "color": [102, 76, 51],
+            "transparency": 0,
+            "states": [{ "name": "axis", "values": ["x", "y", "z"] }]
+        }
+    }"#;
+
+    #[test]
+    fn formats_agree() {
+        let json = BlockRegistryFile::from_str(JSON, RegistryFormat::Json).unwrap();
+        assert_eq!(BlockRegistryFile::from_str(RON, RegistryFormat::Ron).unwrap(), json);
+        assert_eq!(BlockRegistryFile::from_str(TOML, RegistryFormat::Toml).unwrap(), json);
+
+        let log = json.declaration(2).unwrap();
+        assert_eq!(log.state_count(), 3);
+        assert_eq!(json.declaration(0).unwrap().friction(), 0.6);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let mut file = BlockRegistryFile::from_registry(&registry);
+        let log = BlockRegistryFile::from_str(JSON, RegistryFormat::Json).unwrap().declaration(2).cloned().unwrap();
+        file.insert(2, log);
+
+        for format in RegistryFormat::ALL.iter() {
+            let written = file.to_string(*format).unwrap();
+            assert_eq!(BlockRegistryFile::from_str(&written, *format).unwrap(), file, "{}", written);
+        }
+
+        // Defaults stay short instead of spelling out the float's exact value.
+        assert!(file.to_string(RegistryFormat::Toml).unwrap().contains("friction = 0.6\n"));
+
+        let path = std::env::temp_dir().join(format!("voxel-format-{}.toml", std::process::id()));
+        file.to_file(&path).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().contains("[2.states]"));
+        let (loaded, failures) = BlockRegistry::from_file(&path).unwrap();
+        assert!(failures.is_empty());
+        assert_eq!(loaded.block_by_name("core:log").map(|block| block.id()), Some(2));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn format_errors() {
+        assert_eq!(RegistryFormat::from_path("blocks/registry.TOML"), Some(RegistryFormat::Toml));
+        assert_eq!(RegistryFormat::from_path(Path::new("registry.ron")), Some(RegistryFormat::Ron));
+        assert_eq!(RegistryFormat::from_path("registry"), None);
+        assert_eq!(RegistryFormat::from_path("registry.yaml"), None);
+
+        let error = BlockRegistryFile::from_str("[0]\ngroup = \"Empty\"\nname = \n", RegistryFormat::Toml).unwrap_err();
+        assert_eq!(error.position().map(|(line, _)| line), Some(3));
+        assert!(error.to_string().starts_with("invalid TOML: "));
+
+        let error = BlockRegistryFile::from_str("{\n  0: (\n    group: ,\n", RegistryFormat::Ron).unwrap_err();
+        assert_eq!(error.position().map(|(line, _)| line), Some(3));
+
+        let duplicate = r#"{ "1": (group: "A", name: "A", color: (0, 0, 0), transparency: 0), 1: (group: "B", name: "B", color: (0, 0, 0), transparency: 0) }"#;
+        let error = BlockRegistryFile::from_str(duplicate, RegistryFormat::Ron).unwrap_err();
+        assert!(error.to_string().contains("block 1 is declared more than once"));
+    }
+}
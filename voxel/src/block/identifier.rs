@@ -0,0 +1,76 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Namespace used for identifiers that don't specify one.
+pub const DEFAULT_NAMESPACE: &str = "core";
+
+/// Namespaced name of a block such as `core:dirt`.
+///
+/// Both parts may only contain lowercase ascii letters, digits, `_`, `-` and `.`, the path may
+/// additionally contain `/`.
+#[derive(Debug, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct BlockIdentifier {
+    namespace: String,
+    path: String,
+}
+
+impl BlockIdentifier {
+    pub fn new(namespace: &str, path: &str) -> Result<BlockIdentifier, String> {
+        let valid = |part: &str, extra: &[char]| {
+            !part.is_empty()
+                && part.chars().all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c) || extra.contains(&c)
+                })
+        };
+
+        if !valid(namespace, &[]) {
+            return Err(format!("invalid namespace {:?}", namespace));
+        }
+
+        if !valid(path, &['/']) {
+            return Err(format!("invalid path {:?}", path));
+        }
+
+        Ok(BlockIdentifier {
+            namespace: namespace.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+
+    /// Identifier in the default namespace derived from a display name, `Oak Log` becomes
+    /// `core:oak_log`.
+    pub fn from_name(name: &str) -> Result<BlockIdentifier, String> {
+        let path: String = name
+            .trim()
+            .chars()
+            .map(|c| if c.is_whitespace() { '_' } else { c.to_ascii_lowercase() })
+            .collect();
+        BlockIdentifier::new(DEFAULT_NAMESPACE, &path)
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl FromStr for BlockIdentifier {
+    type Err = String;
+
+    /// Parses `namespace:path`, or just `path` in the default namespace.
+    fn from_str(identifier: &str) -> Result<BlockIdentifier, String> {
+        match identifier.find(':') {
+            Some(split) => BlockIdentifier::new(&identifier[..split], &identifier[split + 1..]),
+            None => BlockIdentifier::new(DEFAULT_NAMESPACE, identifier),
+        }
+    }
+}
+
+impl fmt::Display for BlockIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.path)
+    }
+}
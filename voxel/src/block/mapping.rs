@@ -0,0 +1,254 @@
+//! Keeping saved blocks meaningful when registry ids change.
+//!
+//! Saved data stores an `IdMapping` of the registry it was written with. When it is loaded again a
+//! `BlockRemapper` translates the saved ids to whichever ids the same identifiers have now, and
+//! blocks whose identifier no longer exists become a placeholder block.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::block::{Block, BlockSize, BlockRegistry, BlockIdentifier};
+use crate::chunk::{Chunk, ChunkMut, LocalBlockPosition, CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a hash of `(id, identifier)` pairs, which have to be sorted by id.
+pub(crate) fn fingerprint<'a, I: IntoIterator<Item = (BlockSize, &'a str)>>(blocks: I) -> u64 {
+    let mut hash = FNV_OFFSET;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    };
+
+    for (id, identifier) in blocks {
+        // Always 8 bytes so the fingerprint doesn't depend on the block id width.
+        write(&(id as u64).to_le_bytes());
+        write(identifier.as_bytes());
+        // Identifiers can't contain 0xff, so pairs can't run into each other.
+        write(&[0xff]);
+    }
+
+    hash
+}
+
+/// The identifier every block id had in the registry some data was saved with.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IdMapping {
+    fingerprint: u64,
+    identifiers: BTreeMap<BlockSize, String>,
+}
+
+impl IdMapping {
+    pub fn from_registry(registry: &BlockRegistry) -> IdMapping {
+        let identifiers = registry
+            .declarations()
+            .filter_map(|(block, declaration)| {
+                declaration.identifier().ok().map(|identifier| (block.id(), identifier.to_string()))
+            })
+            .collect();
+
+        IdMapping {
+            fingerprint: registry.fingerprint(),
+            identifiers,
+        }
+    }
+
+    /// Fingerprint of the registry the mapping was made from.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Whether blocks saved with this mapping have the same ids in `registry`.
+    pub fn matches(&self, registry: &BlockRegistry) -> bool {
+        self.fingerprint == registry.fingerprint()
+    }
+
+    pub fn identifier(&self, id: BlockSize) -> Option<&str> {
+        self.identifiers.get(&id).map(|identifier| identifier.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.identifiers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.identifiers.is_empty()
+    }
+
+    pub fn remapper(&self, registry: &BlockRegistry, placeholder: Block) -> BlockRemapper {
+        BlockRemapper::new(self, registry, placeholder)
+    }
+}
+
+/// Translates blocks saved with an `IdMapping` to the ids of the current registry.
+#[derive(Debug, Clone)]
+pub struct BlockRemapper {
+    ids: HashMap<BlockSize, BlockSize>,
+    placeholder: Block,
+    identity: bool,
+    missing: Vec<(BlockSize, String)>,
+}
+
+impl BlockRemapper {
+    /// Remapper from `saved` to `registry`, where blocks that no longer exist become
+    /// `placeholder`.
+    pub fn new(saved: &IdMapping, registry: &BlockRegistry, placeholder: Block) -> BlockRemapper {
+        let mut ids = HashMap::new();
+        let mut missing = Vec::new();
+
+        for (id, identifier) in &saved.identifiers {
+            let current = identifier
+                .parse::<BlockIdentifier>()
+                .ok()
+                .and_then(|identifier| registry.block_by_identifier(&identifier));
+
+            match current {
+                Some(block) => {
+                    ids.insert(*id, block.id());
+                },
+                None => {
+                    warn!(util::LOG, "saved block {} ({}) is no longer declared", id, identifier);
+                    missing.push((*id, identifier.clone()));
+                },
+            }
+        }
+
+        BlockRemapper {
+            identity: saved.matches(registry),
+            ids,
+            placeholder,
+            missing,
+        }
+    }
+
+    /// Whether every block in the saved mapping keeps its id.
+    ///
+    /// Blocks with ids that weren't in the saved mapping still become the placeholder.
+    pub fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    pub fn placeholder(&self) -> Block {
+        self.placeholder
+    }
+
+    /// Saved ids and identifiers that aren't declared in the current registry.
+    pub fn missing(&self) -> &[(BlockSize, String)] {
+        &self.missing
+    }
+
+    /// The current block for a saved one, keeping its state.
+    ///
+    /// Blocks that are missing or weren't in the saved mapping at all become the placeholder.
+    pub fn remap(&self, block: Block) -> Block {
+        match self.ids.get(&block.id()) {
+            Some(id) => Block::with_state(*id, block.state()),
+            None => self.placeholder,
+        }
+    }
+
+    /// Remap every block of a chunk in place, returning how many blocks changed.
+    pub fn remap_chunk<C: Chunk + ChunkMut>(&self, chunk: &mut C) -> usize {
+        let mut changed = 0;
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let position = LocalBlockPosition::unchecked_new(x, y, z);
+                    let block = chunk.block(&position);
+                    let remapped = self.remap(block);
+                    if remapped != block {
+                        chunk.set_block(&position, remapped);
+                        changed += 1;
+                    }
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::{Block, BlockRegistry, IdMapping};
+    use crate::chunk::{Chunk, ChunkMut, PalettedChunk, LocalBlockPosition};
+
+    const SAVED: &str = r#"{
+        "0": { "identifier": "core:air", "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "identifier": "core:dirt", "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 },
+        "2": { "identifier": "core:stone", "group": "Stone", "name": "Stone", "color": [128, 128, 128], "transparency": 0 },
+        "3": { "identifier": "core:marble", "group": "Stone", "name": "Marble", "color": [230, 230, 230], "transparency": 0 }
+    }"#;
+
+    // Stone and dirt swapped places, marble was removed and the placeholder was added.
+    const CURRENT: &str = r#"{
+        "0": { "identifier": "core:air", "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "identifier": "core:stone", "group": "Stone", "name": "Stone", "color": [128, 128, 128], "transparency": 0 },
+        "2": { "identifier": "core:dirt", "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 },
+        "9": { "identifier": "core:missing", "group": "Missing", "name": "Missing", "color": [255, 0, 255], "transparency": 0 }
+    }"#;
+
+    #[test]
+    fn fingerprint() {
+        let (saved, _) = BlockRegistry::from_str(SAVED).unwrap();
+        let (reloaded, _) = BlockRegistry::from_str(SAVED).unwrap();
+        let (current, _) = BlockRegistry::from_str(CURRENT).unwrap();
+
+        assert_eq!(saved.fingerprint(), reloaded.fingerprint());
+        assert_ne!(saved.fingerprint(), current.fingerprint());
+
+        // Only ids and identifiers matter, not the rest of the declaration.
+        let (recolored, _) = BlockRegistry::from_str(&SAVED.replace("[165, 42, 42]", "[1, 2, 3]")).unwrap();
+        assert_eq!(saved.fingerprint(), recolored.fingerprint());
+
+        let mapping = IdMapping::from_registry(&saved);
+        assert!(mapping.matches(&reloaded));
+        assert!(!mapping.matches(&current));
+        let remapper = mapping.remapper(&reloaded, Block::hard_create(3));
+        assert!(remapper.is_identity());
+        assert_eq!(remapper.remap(Block::with_state(1, 2)), Block::with_state(1, 2));
+        // Ids the mapping doesn't know are the placeholder even when nothing else moved.
+        assert_eq!(remapper.remap(Block::hard_create(7)), Block::hard_create(3));
+
+        let mut chunk = PalettedChunk::filled(Block::hard_create(2));
+        chunk.set_block(&LocalBlockPosition::unchecked_new(1, 2, 3), Block::hard_create(7));
+        assert_eq!(remapper.remap_chunk(&mut chunk), 1);
+        assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(1, 2, 3)), Block::hard_create(3));
+
+        let json = serde_json::to_string(&mapping).unwrap();
+        assert_eq!(serde_json::from_str::<IdMapping>(&json).unwrap(), mapping);
+    }
+
+    #[test]
+    fn remap() {
+        let (saved, _) = BlockRegistry::from_str(SAVED).unwrap();
+        let (current, _) = BlockRegistry::from_str(CURRENT).unwrap();
+        let mapping = IdMapping::from_registry(&saved);
+
+        let placeholder = current.block_by_name("core:missing").unwrap();
+        let remapper = mapping.remapper(&current, placeholder);
+        assert!(!remapper.is_identity());
+        assert_eq!(remapper.missing(), &[(3, "core:marble".to_owned())]);
+
+        assert_eq!(remapper.remap(Block::hard_create(0)), Block::hard_create(0));
+        assert_eq!(remapper.remap(Block::with_state(1, 4)), Block::with_state(2, 4));
+        assert_eq!(remapper.remap(Block::hard_create(2)), Block::hard_create(1));
+        assert_eq!(remapper.remap(Block::hard_create(3)), placeholder);
+        // Not in the saved mapping at all.
+        assert_eq!(remapper.remap(Block::hard_create(7)), placeholder);
+
+        let mut chunk = PalettedChunk::filled(Block::hard_create(1));
+        let marble = LocalBlockPosition::unchecked_new(5, 6, 7);
+        let air = LocalBlockPosition::unchecked_new(0, 0, 0);
+        chunk.set_block(&marble, Block::hard_create(3));
+        chunk.set_block(&air, Block::hard_create(0));
+
+        let changed = remapper.remap_chunk(&mut chunk);
+        assert_eq!(changed, 64 * 64 * 64 - 1);
+        assert_eq!(chunk.block(&marble), placeholder);
+        assert_eq!(chunk.block(&air), Block::hard_create(0));
+        assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(63, 63, 63)), Block::hard_create(2));
+    }
+}
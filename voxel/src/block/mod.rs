@@ -1,6 +1,19 @@
 
-pub use block::{Block, BlockSize, MAX_BLOCK_ID, EMPTY_BLOCK};
-pub use registry::{BlockRegistry, BlockDeclaration, BlockRegistryFile};
+pub use block::{Block, BlockSize, BlockState, MAX_BLOCK_ID, MAX_BLOCK_STATES, EMPTY_BLOCK};
+pub use identifier::{BlockIdentifier, DEFAULT_NAMESPACE};
+pub use registry::{BlockRegistry, BlockDeclaration, BlockRegistryFile, StateDeclaration, TextureDeclaration};
+pub use registry::{DEFAULT_HARDNESS, DEFAULT_LIGHT, DEFAULT_FRICTION, OverridePolicy};
+pub use error::{RegistryError, DeclarationError, FailedDeclaration, Conflict};
+pub use format::RegistryFormat;
+pub use mapping::{IdMapping, BlockRemapper};
+pub use pack::RegistryBuilder;
+pub use watcher::{RegistryWatcher, RegistryDiff, RegistryEvent, RegistryReload, WatcherHandle};
 
 pub mod block;
+pub mod error;
+pub mod format;
+pub mod identifier;
+pub mod mapping;
+pub mod pack;
 pub mod registry;
+pub mod watcher;
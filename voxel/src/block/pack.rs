@@ -0,0 +1,151 @@
+//! Layering several registry files on top of each other, such as base content followed by the
+//! content packs that extend it.
+//!
+//! ```ignore
+//! let (registry, failures) = RegistryBuilder::new()
+//!     .add_file("resources/registry.json", OverridePolicy::Reject)?
+//!     .add_file("packs/caves/registry.json", OverridePolicy::Reject)?
+//!     .add_file("packs/tweaks/registry.json", OverridePolicy::Override)?
+//!     .build();
+//! ```
+//!
+//! Every declaration remembers which file it came from, see `BlockRegistry::source`.
+
+use std::path::Path;
+
+use crate::block::{BlockRegistry, BlockRegistryFile, FailedDeclaration};
+use crate::block::RegistryError;
+use crate::block::registry::OverridePolicy;
+
+pub struct RegistryBuilder {
+    registry: BlockRegistry,
+    failures: Vec<FailedDeclaration>,
+}
+
+impl RegistryBuilder {
+    pub fn new() -> RegistryBuilder {
+        RegistryBuilder::with_registry(BlockRegistry::empty())
+    }
+
+    /// Start layering on top of an already loaded registry.
+    pub fn with_registry(registry: BlockRegistry) -> RegistryBuilder {
+        RegistryBuilder {
+            registry,
+            failures: Vec::new(),
+        }
+    }
+
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P, policy: OverridePolicy) -> Result<&mut RegistryBuilder, RegistryError> {
+        let file = BlockRegistryFile::from_file(path.as_ref())?;
+        Ok(self.add(&file, Some(path.as_ref()), policy))
+    }
+
+    pub fn add(&mut self, file: &BlockRegistryFile, source: Option<&Path>, policy: OverridePolicy) -> &mut RegistryBuilder {
+        let failures = file.merge_into(&mut self.registry, source, policy);
+        self.failures.extend(failures);
+        self
+    }
+
+    /// Failures of every layer added so far.
+    pub fn failures(&self) -> &[FailedDeclaration] {
+        &self.failures
+    }
+
+    pub fn registry(&self) -> &BlockRegistry {
+        &self.registry
+    }
+
+    pub fn build(self) -> (BlockRegistry, Vec<FailedDeclaration>) {
+        (self.registry, self.failures)
+    }
+}
+
+impl Default for RegistryBuilder {
+    fn default() -> RegistryBuilder {
+        RegistryBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use crate::block::Block;
+    use crate::block::pack::RegistryBuilder;
+    use crate::block::registry::OverridePolicy;
+
+    const BASE: &str = r#"{
+        "0": { "identifier": "core:air", "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "identifier": "core:dirt", "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 }
+    }"#;
+
+    // Claims the base's id 1 and identifier `core:air`.
+    const PACK: &str = r#"{
+        "1": { "identifier": "caves:moss", "group": "Dirt", "name": "Moss", "color": [40, 120, 40], "transparency": 0 },
+        "2": { "identifier": "core:air", "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "3": { "identifier": "caves:crystal", "group": "Crystal", "name": "Crystal", "color": [200, 100, 255], "transparency": 100 }
+    }"#;
+
+    const TWEAKS: &str = r#"{
+        "1": { "identifier": "core:dirt", "group": "Dirt", "name": "Dirt", "color": [120, 80, 40], "transparency": 0 }
+    }"#;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("voxel-pack-{}-{}.json", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn layered_registry() {
+        let base = temp_file("base", BASE);
+        let pack = temp_file("pack", PACK);
+        let tweaks = temp_file("tweaks", TWEAKS);
+
+        let mut builder = RegistryBuilder::new();
+        builder
+            .add_file(&base, OverridePolicy::Reject).unwrap()
+            .add_file(&pack, OverridePolicy::Reject).unwrap()
+            .add_file(&tweaks, OverridePolicy::Override).unwrap();
+        let (registry, failures) = builder.build();
+
+        // The pack's conflicting declarations are rejected and point back at the base.
+        assert_eq!(failures.len(), 2);
+        for failure in &failures {
+            assert_eq!(failure.source(), Some(pack.as_path()));
+            let conflict = failure.conflict().unwrap();
+            assert_eq!(conflict.source.as_deref(), Some(base.as_path()));
+        }
+
+        let id_conflict = failures.iter().find(|failure| failure.id() == 1).unwrap();
+        assert_eq!(id_conflict.conflict().unwrap().id, 1);
+        let name_conflict = failures.iter().find(|failure| failure.id() == 2).unwrap();
+        assert_eq!(name_conflict.conflict().unwrap().id, 0);
+
+        // Explicit overrides replace the earlier declaration.
+        let dirt = Block::hard_create(1);
+        assert_eq!(registry.declaration(dirt).as_ref().unwrap().color(), (120, 80, 40));
+        assert_eq!(registry.source(dirt), Some(tweaks.as_path()));
+        assert_eq!(registry.source(Block::hard_create(0)), Some(base.as_path()));
+        assert_eq!(registry.source(Block::hard_create(3)), Some(pack.as_path()));
+        assert_eq!(registry.block_by_name("caves:crystal"), Some(Block::hard_create(3)));
+        assert!(registry.declaration(Block::hard_create(2)).is_none());
+
+        for path in &[base, pack, tweaks] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn override_keeps_names_unique() {
+        let mut builder = RegistryBuilder::new();
+        builder.add(&serde_json::from_str(BASE).unwrap(), None, OverridePolicy::Reject);
+        builder.add(&serde_json::from_str(PACK).unwrap(), Some(Path::new("pack.json")), OverridePolicy::Override);
+
+        // Overriding id 1 is fine but `core:air` still belongs to block 0.
+        let failed: Vec<_> = builder.failures().iter().map(|failure| failure.id()).collect();
+        assert_eq!(failed, vec![2]);
+        assert_eq!(builder.registry().block_by_name("caves:moss"), Some(Block::hard_create(1)));
+        assert_eq!(builder.registry().block_by_name("core:dirt"), None);
+    }
+}
@@ -1,37 +1,231 @@
-//! Basic structure of a registry file:
+//! Registries can be written in JSON, RON or TOML, see `RegistryFormat`. Basic structure of a
+//! registry file in RON:
 //!
 //! ```ignore
+//! // Lets optional properties be written without `Some(..)`.
+//! #![enable(implicit_some)]
 //! {
-//!     // Block ids are stored in a map instead of array for easier manual lookup.
+//!     // Block ids are stored in a map instead of array for easier manual lookup. They can be
+//!     // written as numbers or strings, TOML only allows the latter.
 //!
-//!     0: {
+//!     0: (
+//!         identifier: "core:air", // Unique `namespace:path`, defaults to `core:` and the snake cased name.
 //!         group: "Empty",
-//!         name: "Air", 
+//!         name: "Air",
 //!         color: (0, 0, 0), // RGB (0-255, 0-255, 0-255)
 //!         transparency: 255, // Transparency of block (0-255)
-//!         collidable: 0, // Whether the player moves through the block // (0 = not collidable, 255 = fully stable) 
+//!         collidable: 0, // Whether the player moves through the block // (0 = not collidable, 255 = fully stable)
 //!         hardness: 255, // Destructability (255 = indestructible)
-//!     },
+//!         light: 0, // Light emitted by the block (0 = none, 255 = brightest)
+//!         friction: 0.6, // Friction of the block's surface, 0.6 for most blocks
+//!     ),
+//!
+//!     // Textures are PNG paths relative to the texture directory, see `AtlasBuilder`. `all` is
+//!     // used for faces that don't name their own.
+//!     1: (
+//!         group: "Dirt",
+//!         name: "Grass",
+//!         color: (60, 160, 60), // Still used by untextured meshes.
+//!         transparency: 0,
+//!         textures: (
+//!             all: "dirt.png",
+//!             top: "grass_top.png",
+//!             side: "grass_side.png",
+//!         ),
+//!     ),
+//!
+//!     // Every property after `transparency` is optional. `collidable` defaults to 255 for visible
+//!     // blocks and 0 otherwise, the others default to `DEFAULT_HARDNESS`, `DEFAULT_LIGHT` and
+//!     // `DEFAULT_FRICTION`.
+//!
+//!     // Blocks can declare states, every combination of their values is a separate
+//!     // `BlockState` of the same block id.
+//!     2: (
+//!         group: "Wood",
+//!         name: "Log",
+//!         color: (102, 76, 51),
+//!         transparency: 0,
+//!         states: [
+//!             (name: "axis", values: ["x", "y", "z"]),
+//!         ],
+//!     ),
 //! }
 //! ```
 
-use std::path::Path;
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
 use std::io;
 
-use crate::block::{Block, BlockSize, MAX_BLOCK_ID};
+use serde::{Deserializer, Serializer};
+use serde::de::{Error, MapAccess, Unexpected, Visitor};
+use serde::ser::SerializeMap;
+
+use crate::block::{Block, BlockSize, BlockState, BlockIdentifier, MAX_BLOCK_ID, MAX_BLOCK_STATES};
+use crate::block::error::{RegistryError, DeclarationError, FailedDeclaration, Conflict};
+use crate::block::format::RegistryFormat;
+use crate::block::mapping;
+use crate::mesh::Face;
+
+pub const DEFAULT_HARDNESS: u8 = 32;
+pub const DEFAULT_LIGHT: u8 = 0;
+pub const DEFAULT_FRICTION: f32 = 0.6;
+
+/// Declarations of ids below this are stored in a flat list, larger ids are stored sparsely so
+/// wide block ids don't need a slot for every possible id.
+const DENSE_BLOCKS: usize = 1 << 16;
+
+const UNDECLARED: &Option<BlockDeclaration> = &None;
+
+fn default_hardness() -> u8 {
+    DEFAULT_HARDNESS
+}
+
+fn default_light() -> u8 {
+    DEFAULT_LIGHT
+}
+
+fn default_friction() -> f32 {
+    DEFAULT_FRICTION
+}
+
+pub(crate) fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(u8, u8, u8), D::Error> {
+    // Either a list or a tuple, depending on the format.
+    struct ColorVisitor;
+
+    impl<'de> Visitor<'de> for ColorVisitor {
+        type Value = Vec<i64>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("an RGB color")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<i64>, A::Error> {
+            let mut components = Vec::new();
+            while let Some(component) = seq.next_element()? {
+                components.push(component);
+            }
+            Ok(components)
+        }
+    }
+
+    let components = deserializer.deserialize_any(ColorVisitor)?;
+    match components[..] {
+        [r, g, b] if components.iter().all(|c| (0..=255).contains(c)) => Ok((r as u8, g as u8, b as u8)),
+        _ => Err(D::Error::custom(format!("invalid color {:?}, expected 3 components from 0 to 255", components))),
+    }
+}
+
+// Written as the shortest decimal that reads back as the same `f32`, rather than its exact value.
+fn serialize_friction<S: Serializer>(friction: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+    let shortest = friction.to_string().parse().unwrap_or(*friction as f64);
+    serializer.serialize_f64(shortest)
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BlockDeclaration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identifier: Option<String>,
     group: String,
     name: String,
+    #[serde(deserialize_with = "deserialize_color")]
     color: (u8, u8, u8),
     transparency: u8,
-    //collidable: u8,
-    //hardness: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    collidable: Option<u8>,
+    #[serde(default = "default_hardness")]
+    hardness: u8,
+    #[serde(default = "default_light")]
+    light: u8,
+    #[serde(default = "default_friction", serialize_with = "serialize_friction")]
+    friction: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    states: Vec<StateDeclaration>,
+    #[serde(default, skip_serializing_if = "TextureDeclaration::is_empty")]
+    textures: TextureDeclaration,
+}
+
+/// Textures of a block's faces, `all` is used for any face that isn't given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureDeclaration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    all: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    top: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bottom: Option<String>,
+    /// The four faces along the x and z axes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    side: Option<String>,
+}
+
+impl TextureDeclaration {
+    pub fn is_empty(&self) -> bool {
+        self.all.is_none() && self.top.is_none() && self.bottom.is_none() && self.side.is_none()
+    }
+
+    pub fn face(&self, face: Face) -> Option<&str> {
+        let specific = match face {
+            Face::PosY => self.top.as_ref(),
+            Face::NegY => self.bottom.as_ref(),
+            _ => self.side.as_ref(),
+        };
+
+        specific.or(self.all.as_ref()).map(|texture| texture.as_str())
+    }
+
+    /// Every texture named, including ones that no face ends up using.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        vec![&self.all, &self.top, &self.bottom, &self.side]
+            .into_iter()
+            .filter_map(|texture| texture.as_ref().map(|texture| texture.as_str()))
+    }
+}
+
+/// A property of a block, such as its orientation, and the values it can take.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateDeclaration {
+    name: String,
+    values: Vec<String>,
+}
+
+impl StateDeclaration {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
 }
 
 impl BlockDeclaration {
+    /// Declaration without states or textures, with everything not given left at its default.
+    pub fn new(group: &str, name: &str, color: (u8, u8, u8), transparency: u8) -> BlockDeclaration {
+        BlockDeclaration {
+            identifier: None,
+            group: group.to_owned(),
+            name: name.to_owned(),
+            color,
+            transparency,
+            collidable: None,
+            hardness: DEFAULT_HARDNESS,
+            light: DEFAULT_LIGHT,
+            friction: DEFAULT_FRICTION,
+            states: Vec::new(),
+            textures: TextureDeclaration::default(),
+        }
+    }
+
+    pub fn with_identifier(mut self, identifier: &BlockIdentifier) -> BlockDeclaration {
+        self.identifier = Some(identifier.to_string());
+        self
+    }
+
     pub fn visible(&self) -> bool {
         self.transparency != 255
     }
@@ -43,33 +237,395 @@ impl BlockDeclaration {
     pub fn transparency(&self) -> u8 {
         self.transparency
     }
+
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Declared identifier, or one derived from the name if none was declared.
+    pub fn identifier(&self) -> Result<BlockIdentifier, String> {
+        match self.identifier.as_ref() {
+            Some(identifier) => identifier.parse(),
+            None => BlockIdentifier::from_name(&self.name),
+        }
+    }
+
+    pub fn color(&self) -> (u8, u8, u8) {
+        self.color
+    }
+
+    /// How solid the block is to move through, 0 is not collidable and 255 is fully stable.
+    pub fn collidable(&self) -> u8 {
+        match self.collidable {
+            Some(collidable) => collidable,
+            None if self.visible() => 255,
+            None => 0,
+        }
+    }
+
+    pub fn passable(&self) -> bool {
+        self.collidable() == 0
+    }
+
+    pub fn hardness(&self) -> u8 {
+        self.hardness
+    }
+
+    pub fn indestructible(&self) -> bool {
+        self.hardness == 255
+    }
+
+    pub fn light(&self) -> u8 {
+        self.light
+    }
+
+    pub fn emits_light(&self) -> bool {
+        self.light > 0
+    }
+
+    pub fn friction(&self) -> f32 {
+        self.friction
+    }
+
+    pub fn states(&self) -> &[StateDeclaration] {
+        &self.states
+    }
+
+    pub fn textures(&self) -> &TextureDeclaration {
+        &self.textures
+    }
+
+    /// Texture of one of the block's faces, if it has any.
+    pub fn texture(&self, face: Face) -> Option<&str> {
+        self.textures.face(face)
+    }
+
+    /// Number of distinct states of this block, 1 if it declares none.
+    pub fn state_count(&self) -> usize {
+        self.states.iter().map(|state| state.values.len()).product()
+    }
+
+    /// Value of the `property` state in the encoded `state`.
+    ///
+    /// States are encoded with the first declared property varying fastest.
+    pub fn state_value(&self, state: BlockState, property: &str) -> Option<&str> {
+        if state as usize >= self.state_count() {
+            return None;
+        }
+
+        let mut remaining = state as usize;
+        for declaration in &self.states {
+            let count = declaration.values.len();
+            if declaration.name == property {
+                return Some(&declaration.values[remaining % count]);
+            }
+            remaining /= count;
+        }
+
+        None
+    }
+
+    /// Encode the given property values into a state, properties that aren't given take their
+    /// first value.
+    pub fn encode_state(&self, properties: &[(&str, &str)]) -> Option<BlockState> {
+        if properties.iter().any(|(name, _)| !self.states.iter().any(|state| state.name == *name)) {
+            return None;
+        }
+
+        let mut encoded = 0;
+        let mut stride = 1;
+        for declaration in &self.states {
+            let index = match properties.iter().find(|(name, _)| *name == declaration.name) {
+                Some((_, value)) => declaration.values.iter().position(|declared| declared == value)?,
+                None => 0,
+            };
+
+            encoded += index * stride;
+            stride *= declaration.values.len();
+        }
+
+        Some(encoded as BlockState)
+    }
+
+    fn validate_states(&self, id: usize) -> Vec<FailedDeclaration> {
+        let mut failures = Vec::new();
+        for (index, declaration) in self.states.iter().enumerate() {
+            let fail = |error| FailedDeclaration::new(id, Some(index), error);
+            let state = || declaration.name.clone();
+
+            if declaration.values.is_empty() {
+                failures.push(fail(DeclarationError::EmptyState { state: state() }));
+            }
+
+            if self.states[..index].iter().any(|previous| previous.name == declaration.name) {
+                failures.push(fail(DeclarationError::DuplicateState { state: state() }));
+            }
+
+            for (value_index, value) in declaration.values.iter().enumerate() {
+                if declaration.values[..value_index].contains(value) {
+                    failures.push(fail(DeclarationError::DuplicateStateValue { state: state(), value: value.clone() }));
+                }
+            }
+        }
+
+        if failures.is_empty() && self.state_count() > MAX_BLOCK_STATES {
+            failures.push(FailedDeclaration::new(
+                id,
+                None,
+                DeclarationError::TooManyStates { count: self.state_count(), max: MAX_BLOCK_STATES },
+            ));
+        }
+
+        failures
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct BlockRegistryFile(
+    #[serde(deserialize_with = "deserialize_declarations", serialize_with = "serialize_declarations")]
+    HashMap<usize, BlockDeclaration>,
+);
+
+/// Declarations ordered by id, with the ids written as strings for formats that require it.
+struct SortedDeclarations<'a> {
+    declarations: Vec<(usize, &'a BlockDeclaration)>,
+    string_ids: bool,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct BlockRegistryFile(HashMap<usize, BlockDeclaration>);
+impl<'a> SortedDeclarations<'a> {
+    fn new(declarations: &'a HashMap<usize, BlockDeclaration>, string_ids: bool) -> SortedDeclarations<'a> {
+        let mut declarations: Vec<_> = declarations.iter().map(|(id, declaration)| (*id, declaration)).collect();
+        declarations.sort_by_key(|(id, _)| *id);
 
-#[derive(Debug)]
-pub struct FailedDeclaration {
-    id: usize,
-    subtype: Option<usize>,
-    reason: String,
+        SortedDeclarations {
+            declarations,
+            string_ids,
+        }
+    }
+}
+
+impl<'a> serde::Serialize for SortedDeclarations<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.declarations.len()))?;
+        for (id, declaration) in &self.declarations {
+            if self.string_ids {
+                map.serialize_entry(&id.to_string(), declaration)?;
+            } else {
+                map.serialize_entry(id, declaration)?;
+            }
+        }
+        map.end()
+    }
+}
+
+fn serialize_declarations<S: Serializer>(declarations: &HashMap<usize, BlockDeclaration>, serializer: S) -> Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&SortedDeclarations::new(declarations, false), serializer)
+}
+
+fn deserialize_declarations<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<usize, BlockDeclaration>, D::Error> {
+    // Ids are map keys, which are numbers in RON but always strings in JSON and TOML.
+    struct Id(usize);
+
+    impl<'de> serde::Deserialize<'de> for Id {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Id, D::Error> {
+            deserializer.deserialize_any(IdVisitor)
+        }
+    }
+
+    struct IdVisitor;
+
+    impl<'de> Visitor<'de> for IdVisitor {
+        type Value = Id;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a block id")
+        }
+
+        fn visit_u64<E: Error>(self, id: u64) -> Result<Id, E> {
+            usize::try_from(id).map(Id).map_err(|_| E::invalid_value(Unexpected::Unsigned(id), &self))
+        }
+
+        fn visit_i64<E: Error>(self, id: i64) -> Result<Id, E> {
+            usize::try_from(id).map(Id).map_err(|_| E::invalid_value(Unexpected::Signed(id), &self))
+        }
+
+        fn visit_str<E: Error>(self, id: &str) -> Result<Id, E> {
+            id.parse().map(Id).map_err(|_| E::invalid_value(Unexpected::Str(id), &self))
+        }
+    }
+
+    struct DeclarationsVisitor;
+
+    impl<'de> Visitor<'de> for DeclarationsVisitor {
+        type Value = HashMap<usize, BlockDeclaration>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a map of block ids to declarations")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut declarations = HashMap::new();
+            while let Some(Id(id)) = map.next_key()? {
+                // `1` and `"01"` would otherwise silently replace each other.
+                if declarations.contains_key(&id) {
+                    return Err(A::Error::custom(format!("block {} is declared more than once", id)));
+                }
+
+                declarations.insert(id, map.next_value()?);
+            }
+            Ok(declarations)
+        }
+    }
+
+    deserializer.deserialize_map(DeclarationsVisitor)
+}
+
+/// How declarations are treated when an earlier registry file already declared the same id.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OverridePolicy {
+    /// Redeclaring an id fails with a conflict and the earlier declaration is kept.
+    Reject,
+    /// Redeclaring an id replaces the earlier declaration.
+    Override,
 }
 
 impl BlockRegistryFile {
+    /// Load a file in the format its extension names, JSON if it has none.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<BlockRegistryFile, RegistryError> {
+        let format = RegistryFormat::from_path(path.as_ref()).unwrap_or_default();
+        BlockRegistryFile::from_file_format(path, format)
+    }
+
+    pub fn from_file_format<P: AsRef<Path>>(path: P, format: RegistryFormat) -> Result<BlockRegistryFile, RegistryError> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .map_err(|e| RegistryError::io(e).at(path))?;
+
+        BlockRegistryFile::from_reader(io::BufReader::new(file), format)
+            .map_err(|e| e.at(path))
+    }
+
+    pub fn from_reader<R: io::Read>(mut reader: R, format: RegistryFormat) -> Result<BlockRegistryFile, RegistryError> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).map_err(RegistryError::io)?;
+        BlockRegistryFile::from_str(&contents, format)
+    }
+
+    pub fn from_str(string: &str, format: RegistryFormat) -> Result<BlockRegistryFile, RegistryError> {
+        format.deserialize(string)
+    }
+
+    /// Declarations of every block in `registry`.
+    pub fn from_registry(registry: &BlockRegistry) -> BlockRegistryFile {
+        let declarations = registry
+            .declarations()
+            .map(|(block, declaration)| (block.id() as usize, declaration.clone()))
+            .collect();
+
+        BlockRegistryFile(declarations)
+    }
+
+    /// The declarations in `format`, ordered by id.
+    pub fn to_string(&self, format: RegistryFormat) -> Result<String, RegistryError> {
+        // TOML keys have to be strings.
+        let declarations = SortedDeclarations::new(&self.0, format == RegistryFormat::Toml);
+        format.serialize(&declarations)
+    }
+
+    /// Write the file in the format its extension names, JSON if it has none.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), RegistryError> {
+        let path = path.as_ref();
+        let contents = self.to_string(RegistryFormat::from_path(path).unwrap_or_default())?;
+        std::fs::write(path, contents).map_err(|e| RegistryError::io(e).at(path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn declaration(&self, id: usize) -> Option<&BlockDeclaration> {
+        self.0.get(&id)
+    }
+
+    pub fn insert(&mut self, id: usize, declaration: BlockDeclaration) -> Option<BlockDeclaration> {
+        self.0.insert(id, declaration)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<BlockDeclaration> {
+        self.0.remove(&id)
+    }
+
     pub fn into_registry(&self, registry: &mut BlockRegistry) -> Vec<FailedDeclaration> {
+        self.merge_into(registry, None, OverridePolicy::Override)
+    }
+
+    /// Add the declarations of this file to `registry`, recording `source` as where they came
+    /// from.
+    ///
+    /// Identifiers have to be unique regardless of the policy, so declaring an identifier that a
+    /// different id already uses is always a conflict.
+    pub fn merge_into(&self, registry: &mut BlockRegistry, source: Option<&Path>, policy: OverridePolicy) -> Vec<FailedDeclaration> {
         let mut failures = Vec::new();
-        for (id, declaration) in &self.0 {
-            if *id > MAX_BLOCK_ID {
-                failures.push(FailedDeclaration {
-                    id: *id,
-                    subtype: None,
-                    reason: format!("id {} is larger than max block type {}", id, MAX_BLOCK_ID),
-                });
 
+        // Sorted so the lowest id keeps a duplicated name no matter the map order.
+        let mut declarations: Vec<_> = self.0.iter().collect();
+        declarations.sort_by_key(|(id, _)| **id);
+
+        for (id, declaration) in declarations {
+            let block = match Block::try_from(*id) {
+                Ok(block) => block,
+                Err(_) => {
+                    failures.push(FailedDeclaration::new(*id, None, DeclarationError::IdOutOfRange { max: MAX_BLOCK_ID }));
+                    continue;
+                }
+            };
+
+            let identifier = match declaration.identifier() {
+                Ok(identifier) => identifier,
+                Err(reason) => {
+                    let identifier = declaration.identifier.clone().unwrap_or_else(|| declaration.name.clone());
+                    failures.push(FailedDeclaration::new(*id, None, DeclarationError::InvalidIdentifier { identifier, reason }));
+                    continue;
+                }
+            };
+
+            if policy == OverridePolicy::Reject && registry.declaration(block).is_some() {
+                let conflict = Conflict {
+                    id: block.id(),
+                    source: registry.source(block).map(|source| source.to_owned()),
+                };
+                failures.push(FailedDeclaration::new(*id, None, DeclarationError::DuplicateId(conflict)));
+
+                continue;
+            }
+
+            if let Some(error) = registry.identifier_conflict(&identifier, block.id()) {
+                failures.push(FailedDeclaration::new(*id, None, error));
+                continue;
+            }
+
+            let state_failures = declaration.validate_states(*id);
+            if !state_failures.is_empty() {
+                failures.extend(state_failures);
                 continue;
             }
 
-            registry.set_declaration(*id as u16, Some(declaration.clone()));
+            if let Err(error) = registry.set_declaration_from(block.id(), Some(declaration.clone()), source) {
+                failures.push(FailedDeclaration::new(*id, None, error));
+            }
+        }
+
+        for failure in &mut failures {
+            failure.set_source(source);
         }
         failures
     }
@@ -77,75 +633,511 @@ impl BlockRegistryFile {
 
 #[derive(Clone)]
 pub struct BlockRegistry {
+    // Grows up to the largest declared id below `DENSE_BLOCKS`.
     registry: Vec<Option<BlockDeclaration>>,
-    groups: HashMap<String, Vec<usize>>,
+    sparse: BTreeMap<BlockSize, Option<BlockDeclaration>>,
+    // Ids of the declared blocks in each group, sorted.
+    groups: HashMap<String, Vec<BlockSize>>,
+    names: HashMap<BlockIdentifier, BlockSize>,
+    // File each block was declared in, if any.
+    sources: HashMap<BlockSize, PathBuf>,
 }
 
-#[derive(Debug)]
-pub enum RegistryError {
-    JSON(serde_json::Error),
-    IO(io::Error)
+impl std::fmt::Debug for BlockRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockRegistry")
+            .field("declared", &self.declarations().count())
+            .field("groups", &self.groups())
+            .finish()
+    }
 }
 
 impl BlockRegistry {
     pub fn empty() -> BlockRegistry {
         let registry = BlockRegistry {
-            registry: vec![None; MAX_BLOCK_ID],
+            registry: Vec::new(),
+            sparse: BTreeMap::new(),
             groups: HashMap::new(),
+            names: HashMap::new(),
+            sources: HashMap::new(),
         };
 
         registry
     }
 
+    /// Load a registry from JSON, see `BlockRegistryFile` for the other formats.
     pub fn from_reader<R: io::Read>(reader: R) -> Result<(BlockRegistry, Vec<FailedDeclaration>), RegistryError> {
-        let registry_file: BlockRegistryFile = serde_json::from_reader(reader)
-            .map_err(|e| RegistryError::JSON(e))?;
+        let registry_file = BlockRegistryFile::from_reader(reader, RegistryFormat::Json)?;
 
         let mut registry = BlockRegistry::empty();
         let failures = registry_file.into_registry(&mut registry);
         Ok((registry, failures))
     }
 
+    /// Load a registry from JSON, see `BlockRegistryFile` for the other formats.
     pub fn from_str(string: &str) -> Result<(BlockRegistry, Vec<FailedDeclaration>), RegistryError> {
-        let registry_file: BlockRegistryFile = serde_json::from_str(string)
-            .map_err(|e| RegistryError::JSON(e))?;
+        let registry_file = BlockRegistryFile::from_str(string, RegistryFormat::Json)?;
 
         let mut registry = BlockRegistry::empty();
         let failures = registry_file.into_registry(&mut registry);
         Ok((registry, failures))
     }
 
+    /// Load a registry in the format its extension names, JSON if it has none.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<(BlockRegistry, Vec<FailedDeclaration>), RegistryError> {
-        let file = std::fs::File::open(path)
-            .map_err(|e| RegistryError::IO(e))?;
+        let format = RegistryFormat::from_path(path.as_ref()).unwrap_or_default();
+        BlockRegistry::from_file_format(path, format)
+    }
+
+    pub fn from_file_format<P: AsRef<Path>>(path: P, format: RegistryFormat) -> Result<(BlockRegistry, Vec<FailedDeclaration>), RegistryError> {
+        let registry_file = BlockRegistryFile::from_file_format(path.as_ref(), format)?;
 
-        BlockRegistry::from_reader(file)
+        let mut registry = BlockRegistry::empty();
+        let failures = registry_file.merge_into(&mut registry, Some(path.as_ref()), OverridePolicy::Override);
+        Ok((registry, failures))
     }
 
     #[inline]
     pub fn declaration(&self, block: Block) -> &Option<BlockDeclaration> {
-        &self.registry[block.id() as usize]
+        self.slot(block.id())
     }
 
     #[inline]
-    pub fn set_declaration(&mut self, index: BlockSize, declaration: Option<BlockDeclaration>) {
-        info!(util::LOG, "setting block {}: {:?}", index, declaration);
-        self.registry[index as usize] = declaration;
+    fn slot(&self, id: BlockSize) -> &Option<BlockDeclaration> {
+        match self.registry.get(id as usize) {
+            Some(declaration) => declaration,
+            None => self.sparse.get(&id).unwrap_or(UNDECLARED),
+        }
+    }
+
+    /// Replace the declaration of `id`, returning the previous one.
+    fn replace_slot(&mut self, id: BlockSize, declaration: Option<BlockDeclaration>) -> Option<BlockDeclaration> {
+        let index = id as usize;
+        if index >= DENSE_BLOCKS {
+            return match declaration {
+                Some(_) => self.sparse.insert(id, declaration).and_then(|previous| previous),
+                None => self.sparse.remove(&id).and_then(|previous| previous),
+            };
+        }
+
+        if index >= self.registry.len() && declaration.is_some() {
+            self.registry.resize(index + 1, None);
+        }
+
+        match self.registry.get_mut(index) {
+            Some(slot) => std::mem::replace(slot, declaration),
+            None => None,
+        }
+    }
+
+    /// Declare or undeclare the block `index`.
+    ///
+    /// Fails without changing anything if the declaration's identifier is used by another block.
+    #[inline]
+    pub fn set_declaration(&mut self, index: BlockSize, declaration: Option<BlockDeclaration>) -> Result<(), DeclarationError> {
+        self.set_declaration_from(index, declaration, None)
     }
 
-    pub fn blocks_in_group<S: AsRef<String>>(&self, group: S) -> Option<Vec<&BlockDeclaration>> {
-        match self.groups.get(group.as_ref()) {
-            Some(group) => {
-                let mut grouped = Vec::new();
-                for index in group {
-                    if let Some(declaration) = self.registry[*index].as_ref() {
-                        grouped.push(declaration);
+    /// Same as `set_declaration`, recording the file the declaration came from.
+    pub fn set_declaration_from(
+        &mut self,
+        index: BlockSize,
+        declaration: Option<BlockDeclaration>,
+        source: Option<&Path>,
+    ) -> Result<(), DeclarationError> {
+        let identifier = declaration.as_ref().and_then(|declaration| declaration.identifier().ok());
+        if let Some(error) = identifier.as_ref().and_then(|identifier| self.identifier_conflict(identifier, index)) {
+            return Err(error);
+        }
+
+        info!(util::LOG, "setting block {}: {:?}", index, declaration);
+
+        match (declaration.as_ref(), source) {
+            (Some(_), Some(source)) => {
+                self.sources.insert(index, source.to_owned());
+            },
+            _ => {
+                self.sources.remove(&index);
+            },
+        }
+
+        if let Some(declaration) = declaration.as_ref() {
+            let ids = self.groups.entry(declaration.group.clone()).or_default();
+            if let Err(position) = ids.binary_search(&index) {
+                ids.insert(position, index);
+            }
+        }
+
+        let group = declaration.as_ref().map(|declaration| declaration.group.clone());
+
+        if let Some(previous) = self.replace_slot(index, declaration) {
+            if group.as_ref() != Some(&previous.group) {
+                if let Some(ids) = self.groups.get_mut(&previous.group) {
+                    ids.retain(|id| *id != index);
+                    if ids.is_empty() {
+                        self.groups.remove(&previous.group);
                     }
                 }
-                Some(grouped)
-            },
-            None => None,
+            }
+
+            if let Ok(identifier) = previous.identifier() {
+                if self.names.get(&identifier) == Some(&index) {
+                    self.names.remove(&identifier);
+                }
+            }
+        }
+
+        if let Some(identifier) = identifier {
+            self.names.insert(identifier, index);
+        }
+        Ok(())
+    }
+
+    // Error for declaring `identifier` as block `index` if another block already uses it.
+    fn identifier_conflict(&self, identifier: &BlockIdentifier, index: BlockSize) -> Option<DeclarationError> {
+        let existing = *self.names.get(identifier)?;
+        if existing == index {
+            return None;
+        }
+
+        let conflict = Conflict {
+            id: existing,
+            source: self.source(Block::hard_create(existing)).map(|source| source.to_owned()),
+        };
+        Some(DeclarationError::DuplicateIdentifier {
+            identifier: identifier.clone(),
+            conflict,
+        })
+    }
+
+    /// Block with the given identifier such as `core:dirt`, the namespace defaults to `core`.
+    pub fn block_by_name(&self, name: &str) -> Option<Block> {
+        let identifier: BlockIdentifier = name.parse().ok()?;
+        self.block_by_identifier(&identifier)
+    }
+
+    pub fn block_by_identifier(&self, identifier: &BlockIdentifier) -> Option<Block> {
+        self.names.get(identifier).map(|id| Block::hard_create(*id))
+    }
+
+    /// Hash of every declared id along with its identifier, which changes whenever blocks are
+    /// added, removed or renumbered.
+    pub fn fingerprint(&self) -> u64 {
+        let identifiers: Vec<_> = self
+            .declarations()
+            .filter_map(|(block, declaration)| {
+                declaration.identifier().ok().map(|identifier| (block.id(), identifier.to_string()))
+            })
+            .collect();
+
+        mapping::fingerprint(identifiers.iter().map(|(id, identifier)| (*id, identifier.as_str())))
+    }
+
+    /// File the declaration of `block` was loaded from.
+    pub fn source(&self, block: Block) -> Option<&Path> {
+        self.sources.get(&block.id()).map(|source| source.as_path())
+    }
+
+    /// Every declared block in order of their ids.
+    pub fn declarations(&self) -> impl Iterator<Item = (Block, &BlockDeclaration)> {
+        let dense = self.registry
+            .iter()
+            .enumerate()
+            .map(|(id, declaration)| (id as BlockSize, declaration));
+        let sparse = self.sparse.iter().map(|(id, declaration)| (*id, declaration));
+
+        dense.chain(sparse).filter_map(|(id, declaration)| {
+            declaration
+                .as_ref()
+                .map(|declaration| (Block::hard_create(id), declaration))
+        })
+    }
+
+    pub fn identifier(&self, block: Block) -> Option<BlockIdentifier> {
+        self.declaration(block).as_ref()?.identifier().ok()
+    }
+
+    /// Value of the `property` state of `block`, `None` if the block has no such state.
+    pub fn state_value(&self, block: Block, property: &str) -> Option<&str> {
+        self.declaration(block).as_ref()?.state_value(block.state(), property)
+    }
+
+    /// Block `id` with the given state properties set.
+    pub fn block_with_state(&self, id: BlockSize, properties: &[(&str, &str)]) -> Option<Block> {
+        let state = self.slot(id).as_ref()?.encode_state(properties)?;
+        Some(Block::with_state(id, state))
+    }
+
+    /// Whether `block` is declared and its state is in range for the declaration.
+    pub fn valid_state(&self, block: Block) -> bool {
+        match self.declaration(block) {
+            Some(declaration) => (block.state() as usize) < declaration.state_count(),
+            None => false,
+        }
+    }
+
+    /// Names of every group with at least one declared block, sorted.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = self.groups.keys().map(|group| group.as_str()).collect();
+        groups.sort();
+        groups
+    }
+
+    /// Blocks in `group` along with their declarations, in order of their ids.
+    pub fn group_blocks<'a>(&'a self, group: &str) -> impl Iterator<Item = (Block, &'a BlockDeclaration)> + 'a {
+        let ids = self.groups.get(group).map(|ids| ids.as_slice()).unwrap_or(&[]);
+        ids.iter().filter_map(move |id| {
+            self.slot(*id)
+                .as_ref()
+                .map(|declaration| (Block::hard_create(*id), declaration))
+        })
+    }
+
+    pub fn blocks_in_group(&self, group: &str) -> Option<Vec<&BlockDeclaration>> {
+        if !self.groups.contains_key(group) {
+            return None;
         }
+
+        Some(self.group_blocks(group).map(|(_, declaration)| declaration).collect())
     }
 }
 
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use std::convert::TryFrom;
+
+    use crate::block::{Block, BlockSize, BlockRegistry, BlockRegistryFile, RegistryError, DeclarationError, MAX_BLOCK_ID};
+    use crate::block::registry::{DEFAULT_HARDNESS, DEFAULT_LIGHT, DEFAULT_FRICTION};
+
+    const STATES: &str = r#"{
+        "0": { "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "2": {
+            "group": "Wood",
+            "name": "Log",
+            "color": [102, 76, 51],
+            "transparency": 0,
+            "states": [
+                { "name": "axis", "values": ["x", "y", "z"] },
+                { "name": "stripped", "values": ["false", "true"] }
+            ]
+        },
+        "3": {
+            "group": "Wood",
+            "name": "Slab",
+            "color": [102, 76, 51],
+            "transparency": 0,
+            "states": [
+                { "name": "half", "values": ["bottom", "top", "bottom"] },
+                { "name": "waterlogged", "values": [] }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn block_states() {
+        let (registry, failures) = BlockRegistry::from_str(STATES).unwrap();
+
+        let log = registry.declaration(Block::hard_create(2)).as_ref().unwrap();
+        assert_eq!(log.state_count(), 6);
+
+        let block = registry.block_with_state(2, &[("axis", "z"), ("stripped", "true")]).unwrap();
+        assert_eq!(block.state(), 2 + 3);
+        assert_eq!(registry.state_value(block, "axis"), Some("z"));
+        assert_eq!(registry.state_value(block, "stripped"), Some("true"));
+        assert_eq!(registry.state_value(block, "facing"), None);
+        assert!(registry.valid_state(block));
+
+        // Missing properties take their first value.
+        let block = registry.block_with_state(2, &[("stripped", "true")]).unwrap();
+        assert_eq!(registry.state_value(block, "axis"), Some("x"));
+
+        assert!(registry.block_with_state(2, &[("axis", "w")]).is_none());
+        assert!(registry.block_with_state(2, &[("facing", "north")]).is_none());
+        assert!(!registry.valid_state(Block::with_state(2, 6)));
+        assert!(registry.valid_state(Block::hard_create(0)));
+
+        // Both invalid states of the slab are reported and it isn't registered.
+        let mut subtypes: Vec<_> = failures.iter().map(|failure| (failure.id(), failure.subtype())).collect();
+        subtypes.sort();
+        assert_eq!(subtypes, vec![(3, Some(0)), (3, Some(1))]);
+        assert!(registry.declaration(Block::hard_create(3)).is_none());
+    }
+
+    const GROUPS: &str = r#"{
+        "0": { "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 },
+        "4": { "group": "Dirt", "name": "Grass", "color": [60, 160, 60], "transparency": 0 },
+        "2": { "group": "Dirt", "name": "Mud", "color": [90, 60, 40], "transparency": 0 }
+    }"#;
+
+    #[test]
+    fn groups() {
+        let (mut registry, failures) = BlockRegistry::from_str(GROUPS).unwrap();
+        assert!(failures.is_empty());
+        assert_eq!(registry.groups(), vec!["Air", "Dirt"]);
+
+        let dirt: Vec<_> = registry.group_blocks("Dirt").map(|(block, declaration)| (block.id(), declaration.name())).collect();
+        assert_eq!(dirt, vec![(1, "Dirt"), (2, "Mud"), (4, "Grass")]);
+        assert_eq!(registry.blocks_in_group("Dirt").unwrap().len(), 3);
+        assert!(registry.blocks_in_group("Stone").is_none());
+        assert_eq!(registry.group_blocks("Stone").count(), 0);
+
+        // Redeclaring a block moves it between groups and empty groups are dropped.
+        let mut air = registry.declaration(Block::hard_create(0)).clone().unwrap();
+        air.group = "Dirt".to_owned();
+        registry.set_declaration(0, Some(air)).unwrap();
+        registry.set_declaration(2, None).unwrap();
+        assert_eq!(registry.groups(), vec!["Dirt"]);
+
+        let dirt: Vec<_> = registry.group_blocks("Dirt").map(|(block, _)| block.id()).collect();
+        assert_eq!(dirt, vec![0, 1, 4]);
+    }
+
+    const NAMES: &str = r#"{
+        "0": { "identifier": "core:air", "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 },
+        "2": { "identifier": "mod:dirt", "group": "Dirt", "name": "Dirt", "color": [150, 40, 40], "transparency": 0 },
+        "3": { "identifier": "mod:Bad Name", "group": "Dirt", "name": "Bad", "color": [0, 0, 0], "transparency": 0 },
+        "4": { "identifier": "core:dirt", "group": "Dirt", "name": "Other Dirt", "color": [0, 0, 0], "transparency": 0 },
+        "5": { "group": "Wood", "name": "Oak Log", "color": [102, 76, 51], "transparency": 0 }
+    }"#;
+
+    #[test]
+    fn names() {
+        let (mut registry, failures) = BlockRegistry::from_str(NAMES).unwrap();
+
+        assert_eq!(registry.block_by_name("core:air"), Some(Block::hard_create(0)));
+        assert_eq!(registry.block_by_name("dirt"), Some(Block::hard_create(1)));
+        assert_eq!(registry.block_by_name("core:dirt"), Some(Block::hard_create(1)));
+        assert_eq!(registry.block_by_name("mod:dirt"), Some(Block::hard_create(2)));
+        assert_eq!(registry.block_by_name("core:oak_log"), Some(Block::hard_create(5)));
+        assert_eq!(registry.block_by_name("core:stone"), None);
+        assert_eq!(registry.block_by_name("not valid"), None);
+        assert_eq!(registry.identifier(Block::hard_create(2)).unwrap().to_string(), "mod:dirt");
+
+        // The invalid identifier and the duplicated `core:dirt` are rejected.
+        let mut failed: Vec<_> = failures.iter().map(|failure| failure.id()).collect();
+        failed.sort();
+        assert_eq!(failed, vec![3, 4]);
+        assert!(registry.declaration(Block::hard_create(4)).is_none());
+
+        // Taking over an identifier of another block is rejected and changes nothing.
+        let stolen = registry.declaration(Block::hard_create(2)).clone().unwrap();
+        let error = registry.set_declaration(6, Some(stolen)).unwrap_err();
+        assert_eq!(error.conflict().map(|conflict| conflict.id), Some(2));
+        assert!(registry.declaration(Block::hard_create(6)).is_none());
+        assert_eq!(registry.block_by_name("mod:dirt"), Some(Block::hard_create(2)));
+
+        registry.set_declaration(1, None).unwrap();
+        assert_eq!(registry.block_by_name("core:dirt"), None);
+        assert_eq!(registry.block_by_name("mod:dirt"), Some(Block::hard_create(2)));
+    }
+
+    #[test]
+    fn properties() {
+        let (registry, _) = BlockRegistry::from_str(r#"{
+            "0": { "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+            "1": { "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 },
+            "2": {
+                "group": "Light",
+                "name": "Lamp",
+                "color": [255, 255, 200],
+                "transparency": 0,
+                "collidable": 128,
+                "hardness": 255,
+                "light": 200,
+                "friction": 0.2
+            }
+        }"#).unwrap();
+
+        // Older declarations without the properties get defaults.
+        let air = registry.declaration(Block::hard_create(0)).as_ref().unwrap();
+        assert_eq!(air.collidable(), 0);
+        assert!(air.passable());
+        assert_eq!(air.hardness(), DEFAULT_HARDNESS);
+        assert_eq!(air.light(), DEFAULT_LIGHT);
+        assert_eq!(air.friction(), DEFAULT_FRICTION);
+
+        let dirt = registry.declaration(Block::hard_create(1)).as_ref().unwrap();
+        assert_eq!(dirt.collidable(), 255);
+        assert!(!dirt.passable());
+        assert!(!dirt.indestructible());
+        assert!(!dirt.emits_light());
+
+        let lamp = registry.declaration(Block::hard_create(2)).as_ref().unwrap();
+        assert_eq!(lamp.collidable(), 128);
+        assert!(lamp.indestructible());
+        assert_eq!(lamp.light(), 200);
+        assert!(lamp.emits_light());
+        assert_eq!(lamp.friction(), 0.2);
+    }
+
+    #[test]
+    fn errors() {
+        let position = |contents: &str| BlockRegistry::from_str(contents).unwrap_err().position();
+
+        assert_eq!(position("{\n  \"0\": { \"group\": }\n}"), Some((2, 19)));
+
+        // Misspelled properties aren't silently ignored.
+        let unknown = r#"{
+            "0": { "group": "Air", "name": "Air", "colour": [0, 0, 0], "transparency": 255 }
+        }"#;
+        let error = BlockRegistry::from_str(unknown).unwrap_err();
+        assert_eq!(error.position().map(|(line, _)| line), Some(2));
+        assert!(error.to_string().contains("unknown field `colour`"));
+
+        let color = r#"{
+            "0": { "group": "Air", "name": "Air", "color": [0, 300, 0], "transparency": 255 }
+        }"#;
+        assert!(BlockRegistry::from_str(color).unwrap_err().to_string().contains("invalid color [0, 300, 0]"));
+
+        let error = BlockRegistryFile::from_file("does/not/exist.json").err().unwrap();
+        match &error {
+            RegistryError::IO { .. } => {},
+            error => panic!("unexpected error {:?}", error),
+        }
+        assert_eq!(error.path(), Some(Path::new("does/not/exist.json")));
+        assert!(error.to_string().starts_with("does/not/exist.json: "));
+
+        let (_, failures) = BlockRegistry::from_str(NAMES).unwrap();
+        let mut failures: Vec<_> = failures.iter().map(|failure| (failure.id(), failure.error().clone())).collect();
+        failures.sort_by_key(|(id, _)| *id);
+        match &failures[..] {
+            [(3, DeclarationError::InvalidIdentifier { identifier, .. }), (4, DeclarationError::DuplicateIdentifier { conflict, .. })] => {
+                assert_eq!(identifier, "mod:Bad Name");
+                assert_eq!(conflict.id, 1);
+            },
+            failures => panic!("unexpected failures {:?}", failures),
+        }
+
+        let (_, failures) = BlockRegistry::from_str(STATES).unwrap();
+        let messages: Vec<_> = failures.iter().map(|failure| failure.to_string()).collect();
+        assert!(messages.contains(&"block 3 state 0: state \"half\" has duplicate value \"bottom\"".to_owned()));
+        assert!(messages.contains(&"block 3 state 1: state \"waterlogged\" has no values".to_owned()));
+    }
+
+    #[test]
+    fn id_range() {
+        let (registry, failures) = BlockRegistry::from_str(&format!(r#"{{
+            "0": {{ "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 }},
+            "{}": {{ "group": "Last", "name": "Last", "color": [1, 2, 3], "transparency": 0 }},
+            "{}": {{ "group": "Past", "name": "Past", "color": [1, 2, 3], "transparency": 0 }}
+        }}"#, MAX_BLOCK_ID, MAX_BLOCK_ID + 1)).unwrap();
+
+        // The id past the max is rejected instead of wrapping around onto air.
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].id(), MAX_BLOCK_ID + 1);
+        assert_eq!(failures[0].error(), &DeclarationError::IdOutOfRange { max: MAX_BLOCK_ID });
+        assert_eq!(registry.declaration(Block::hard_create(0)).as_ref().unwrap().name(), "Air");
+
+        let last = Block::try_from(MAX_BLOCK_ID).unwrap();
+        assert_eq!(last.id(), BlockSize::MAX);
+        assert!(Block::try_from(MAX_BLOCK_ID + 1).is_err());
+        assert_eq!(registry.block_by_name("last"), Some(last));
+
+        let ids: Vec<_> = registry.declarations().map(|(block, _)| block.id()).collect();
+        assert_eq!(ids, vec![0, BlockSize::MAX]);
+    }
+}
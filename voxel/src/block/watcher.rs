@@ -0,0 +1,337 @@
+//! Reloading a `BlockRegistry` whenever its source file changes.
+//!
+//! The watcher polls the file's contents, so it works the same on every platform and editor.
+//! Whenever the contents change the file is parsed again and subscribers are sent the new registry
+//! along with a `RegistryDiff` of what changed, so that chunks using those blocks can be remeshed.
+//! If the new contents fail to parse, or the file can't be read at all, the previous registry is
+//! kept and the error is sent instead.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+
+use crate::block::{Block, BlockSize, BlockRegistry, BlockRegistryFile, BlockDeclaration, FailedDeclaration};
+use crate::block::{RegistryError, RegistryFormat};
+use crate::block::registry::OverridePolicy;
+
+/// Block ids whose declarations differ between two registries.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RegistryDiff {
+    pub added: Vec<BlockSize>,
+    pub removed: Vec<BlockSize>,
+    pub changed: Vec<BlockSize>,
+}
+
+impl RegistryDiff {
+    pub fn between(old: &BlockRegistry, new: &BlockRegistry) -> RegistryDiff {
+        let declarations = |registry| -> BTreeMap<BlockSize, &BlockDeclaration> {
+            BlockRegistry::declarations(registry)
+                .map(|(block, declaration)| (block.id(), declaration))
+                .collect()
+        };
+
+        let old = declarations(old);
+        let new = declarations(new);
+
+        let mut diff = RegistryDiff::default();
+        for (id, declaration) in &new {
+            match old.get(id) {
+                Some(previous) if previous != declaration => diff.changed.push(*id),
+                Some(_) => {},
+                None => diff.added.push(*id),
+            }
+        }
+
+        diff.removed = old.keys().filter(|id| !new.contains_key(id)).cloned().collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Whether anything using `block` needs to be updated.
+    pub fn affects(&self, block: Block) -> bool {
+        let id = block.id();
+        self.added.contains(&id) || self.removed.contains(&id) || self.changed.contains(&id)
+    }
+}
+
+/// A successfully reloaded registry.
+#[derive(Debug)]
+pub struct RegistryReload {
+    pub registry: Arc<BlockRegistry>,
+    pub diff: RegistryDiff,
+    pub failures: Vec<FailedDeclaration>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RegistryEvent {
+    Reloaded(Arc<RegistryReload>),
+    /// The file changed but couldn't be loaded, the previous registry is still in use.
+    Failed(Arc<RegistryError>),
+}
+
+pub struct RegistryWatcher {
+    path: PathBuf,
+    // Last contents seen, so a bad edit is only reported once.
+    contents: Vec<u8>,
+    // Whether the last read failed, so a missing file is only reported once.
+    unreadable: bool,
+    current: Arc<ArcSwap<BlockRegistry>>,
+    subscribers: Vec<Sender<RegistryEvent>>,
+}
+
+impl RegistryWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<(RegistryWatcher, Vec<FailedDeclaration>), RegistryError> {
+        let path = path.as_ref().to_owned();
+        let contents = std::fs::read(&path).map_err(|e| RegistryError::io(e).at(&path))?;
+        let (registry, failures) = load(&path, &contents)?;
+
+        let watcher = RegistryWatcher {
+            path,
+            contents,
+            unreadable: false,
+            current: Arc::new(ArcSwap::from_pointee(registry)),
+            subscribers: Vec::new(),
+        };
+
+        Ok((watcher, failures))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The most recently loaded registry.
+    pub fn registry(&self) -> Arc<BlockRegistry> {
+        self.current.load_full()
+    }
+
+    /// Shared view of the most recently loaded registry, which stays up to date while watching.
+    pub fn shared(&self) -> Arc<ArcSwap<BlockRegistry>> {
+        self.current.clone()
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<RegistryEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Check the file for changes and reload it if there are any.
+    ///
+    /// Returns `Ok(None)` if the contents are the same as the last time they were read. Errors are
+    /// sent to the subscribers the first time they happen, a file that can't be read keeps
+    /// returning its error without sending it again until it can be read.
+    pub fn poll(&mut self) -> Result<Option<Arc<RegistryReload>>, Arc<RegistryError>> {
+        let contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                let error = Arc::new(RegistryError::io(e).at(&self.path));
+                if !self.unreadable {
+                    self.unreadable = true;
+                    self.fail(error.clone());
+                }
+                return Err(error);
+            },
+        };
+        self.unreadable = false;
+        if contents == self.contents {
+            return Ok(None);
+        }
+
+        let loaded = load(&self.path, &contents);
+        self.contents = contents;
+
+        match loaded {
+            Ok((registry, failures)) => {
+                let diff = RegistryDiff::between(&self.current.load(), &registry);
+                let registry = Arc::new(registry);
+                self.current.store(registry.clone());
+
+                let reload = Arc::new(RegistryReload { registry, diff, failures });
+                info!(util::LOG, "reloaded registry {:?}: {:?}", self.path, reload.diff);
+                if !reload.diff.is_empty() || !reload.failures.is_empty() {
+                    self.notify(RegistryEvent::Reloaded(reload.clone()));
+                }
+                Ok(Some(reload))
+            },
+            Err(error) => {
+                let error = Arc::new(error);
+                self.fail(error.clone());
+                Err(error)
+            },
+        }
+    }
+
+    /// Poll the file every `interval` on a separate thread until the handle is stopped.
+    pub fn watch(mut self, interval: Duration) -> WatcherHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let current = self.current.clone();
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            while !thread_stop.load(Ordering::Acquire) {
+                // Errors are logged and sent to the subscribers by `poll`.
+                let _ = self.poll();
+                thread::sleep(interval);
+            }
+            self
+        });
+
+        WatcherHandle {
+            stop,
+            current,
+            thread,
+        }
+    }
+
+    fn fail(&mut self, error: Arc<RegistryError>) {
+        warn!(util::LOG, "failed to reload registry {:?}: {:?}", self.path, error);
+        self.notify(RegistryEvent::Failed(error));
+    }
+
+    fn notify(&mut self, event: RegistryEvent) {
+        self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+fn load(path: &Path, contents: &[u8]) -> Result<(BlockRegistry, Vec<FailedDeclaration>), RegistryError> {
+    let format = RegistryFormat::from_path(path).unwrap_or_default();
+    let file = BlockRegistryFile::from_reader(contents, format).map_err(|e| e.at(path))?;
+    let mut registry = BlockRegistry::empty();
+    let failures = file.merge_into(&mut registry, Some(path), OverridePolicy::Override);
+    Ok((registry, failures))
+}
+
+pub struct WatcherHandle {
+    stop: Arc<AtomicBool>,
+    current: Arc<ArcSwap<BlockRegistry>>,
+    thread: JoinHandle<RegistryWatcher>,
+}
+
+impl WatcherHandle {
+    pub fn registry(&self) -> Arc<BlockRegistry> {
+        self.current.load_full()
+    }
+
+    /// Stop watching and get the watcher back.
+    pub fn stop(self) -> RegistryWatcher {
+        self.stop.store(true, Ordering::Release);
+        self.thread.join().expect("registry watcher panicked")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::block::Block;
+    use crate::block::watcher::{RegistryWatcher, RegistryEvent};
+
+    const ORIGINAL: &str = r#"{
+        "0": { "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 },
+        "2": { "group": "Stone", "name": "Stone", "color": [128, 128, 128], "transparency": 0 }
+    }"#;
+
+    const EDITED: &str = r#"{
+        "0": { "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "group": "Dirt", "name": "Dirt", "color": [120, 80, 40], "transparency": 0 },
+        "3": { "group": "Sand", "name": "Sand", "color": [220, 200, 150], "transparency": 0 }
+    }"#;
+
+    fn temp_registry(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("voxel-watcher-{}-{}.json", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn poll_reload() {
+        let path = temp_registry("poll", ORIGINAL);
+        let (mut watcher, _) = RegistryWatcher::new(&path).unwrap();
+        let events = watcher.subscribe();
+        assert!(watcher.poll().unwrap().is_none());
+
+        std::fs::write(&path, EDITED).unwrap();
+        let reload = watcher.poll().unwrap().unwrap();
+        assert_eq!(reload.diff.added, vec![3]);
+        assert_eq!(reload.diff.removed, vec![2]);
+        assert_eq!(reload.diff.changed, vec![1]);
+        assert!(reload.diff.affects(Block::hard_create(1)));
+        assert!(!reload.diff.affects(Block::hard_create(0)));
+
+        match events.try_recv().unwrap() {
+            RegistryEvent::Reloaded(event) => assert_eq!(event.diff, reload.diff),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // A broken edit keeps the previous registry and is only reported once.
+        std::fs::write(&path, "{ \"1\": { \"group\": ").unwrap();
+        assert!(watcher.poll().is_err());
+        assert!(watcher.poll().unwrap().is_none());
+        assert!(watcher.registry().declaration(Block::hard_create(3)).is_some());
+        match events.try_recv().unwrap() {
+            RegistryEvent::Failed(_) => {},
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(events.try_recv().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deleted_file() {
+        let path = temp_registry("deleted", ORIGINAL);
+        let (mut watcher, _) = RegistryWatcher::new(&path).unwrap();
+        let events = watcher.subscribe();
+
+        // Reported once while the file is gone, with the previous registry kept.
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.poll().is_err());
+        assert!(watcher.poll().is_err());
+        match events.try_recv().unwrap() {
+            RegistryEvent::Failed(_) => {},
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(events.try_recv().is_err());
+        assert!(watcher.registry().declaration(Block::hard_create(2)).is_some());
+
+        std::fs::write(&path, EDITED).unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap().diff.added, vec![3]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.poll().is_err());
+        assert!(matches!(events.try_recv().unwrap(), RegistryEvent::Reloaded(_)));
+        assert!(matches!(events.try_recv().unwrap(), RegistryEvent::Failed(_)));
+    }
+
+    #[test]
+    fn watch_thread() {
+        let path = temp_registry("watch", ORIGINAL);
+        let (mut watcher, _) = RegistryWatcher::new(&path).unwrap();
+        let events = watcher.subscribe();
+        let handle = watcher.watch(Duration::from_millis(5));
+
+        // Replaced in one step, or the thread could read the file while it's half written.
+        let edited = path.with_extension("json.tmp");
+        std::fs::write(&edited, EDITED).unwrap();
+        std::fs::rename(&edited, &path).unwrap();
+        match events.recv_timeout(Duration::from_secs(10)).unwrap() {
+            RegistryEvent::Reloaded(event) => assert_eq!(event.diff.added, vec![3]),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(handle.registry().declaration(Block::hard_create(3)).is_some());
+
+        let watcher = handle.stop();
+        assert!(watcher.registry().declaration(Block::hard_create(2)).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
-use crate::block::{Block, EMPTY_BLOCK, BlockRegistry, BlockDeclaration};
+use crate::block::{Block, BlockSize, BlockState, EMPTY_BLOCK, BlockRegistry, BlockDeclaration};
+use crate::mesh::{ChunkMesh, MeshMode, Face};
 
 pub const CHUNK_HEIGHT: usize = 64; // Y
 pub const CHUNK_WIDTH: usize = 64; // X
@@ -38,6 +41,18 @@ impl LocalBlockPosition {
         chunk_index(self.x, self.y, self.z)
     }
 
+    pub fn x(&self) -> usize {
+        self.x
+    }
+
+    pub fn y(&self) -> usize {
+        self.y
+    }
+
+    pub fn z(&self) -> usize {
+        self.z
+    }
+
     #[inline]
     pub const fn possible_surrounding(&self) -> [LocalBlockPosition; 6] {
         [
@@ -89,67 +104,395 @@ impl LocalBlockPosition {
     //}
 }
 
+/// Position of a chunk in the world, measured in chunks.
+#[derive(Debug, Copy, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ChunkPosition {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+impl ChunkPosition {
+    pub const fn new(x: i32, y: i32, z: i32) -> ChunkPosition {
+        ChunkPosition { x, y, z }
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn z(&self) -> i32 {
+        self.z
+    }
+
+    /// Panics if the result doesn't fit an `i32`, see `checked_offset`.
+    pub fn offset(&self, x: i32, y: i32, z: i32) -> ChunkPosition {
+        self.checked_offset(x, y, z).expect("chunk position out of range")
+    }
+
+    /// `None` if the result doesn't fit an `i32`.
+    pub fn checked_offset(&self, x: i32, y: i32, z: i32) -> Option<ChunkPosition> {
+        Some(ChunkPosition::new(self.x.checked_add(x)?, self.y.checked_add(y)?, self.z.checked_add(z)?))
+    }
+
+    /// World position of the block at local position (0, 0, 0) in this chunk.
+    pub fn origin(&self) -> WorldBlockPosition {
+        WorldBlockPosition::new(
+            self.x as i64 * CHUNK_WIDTH as i64,
+            self.y as i64 * CHUNK_HEIGHT as i64,
+            self.z as i64 * CHUNK_LENGTH as i64,
+        )
+    }
+}
+
+/// Position of a block in the world, measured in blocks.
+#[derive(Debug, Copy, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct WorldBlockPosition {
+    x: i64,
+    y: i64,
+    z: i64,
+}
+
+impl WorldBlockPosition {
+    pub const fn new(x: i64, y: i64, z: i64) -> WorldBlockPosition {
+        WorldBlockPosition { x, y, z }
+    }
+
+    pub fn from_local(chunk: ChunkPosition, local: &LocalBlockPosition) -> WorldBlockPosition {
+        let origin = chunk.origin();
+        WorldBlockPosition::new(
+            origin.x + local.x as i64,
+            origin.y + local.y as i64,
+            origin.z + local.z as i64,
+        )
+    }
+
+    pub fn x(&self) -> i64 {
+        self.x
+    }
+
+    pub fn y(&self) -> i64 {
+        self.y
+    }
+
+    pub fn z(&self) -> i64 {
+        self.z
+    }
+
+    /// Panics if the result doesn't fit an `i64`.
+    pub fn offset(&self, x: i64, y: i64, z: i64) -> WorldBlockPosition {
+        let add = |a: i64, b: i64| a.checked_add(b).expect("block position out of range");
+        WorldBlockPosition::new(add(self.x, x), add(self.y, y), add(self.z, z))
+    }
+
+    /// Chunk containing this block, rounding towards negative infinity.
+    ///
+    /// Panics if the block is too far out for its chunk to have an `i32` position, see
+    /// `checked_chunk`.
+    pub fn chunk(&self) -> ChunkPosition {
+        self.checked_chunk().expect("chunk position out of range")
+    }
+
+    /// Chunk containing this block, `None` if it is too far out to have an `i32` position.
+    pub fn checked_chunk(&self) -> Option<ChunkPosition> {
+        let chunk = |a: i64, size: usize| i32::try_from(a.div_euclid(size as i64)).ok();
+        Some(ChunkPosition::new(
+            chunk(self.x, CHUNK_WIDTH)?,
+            chunk(self.y, CHUNK_HEIGHT)?,
+            chunk(self.z, CHUNK_LENGTH)?,
+        ))
+    }
+
+    /// Position of this block inside of its chunk.
+    pub fn local(&self) -> LocalBlockPosition {
+        LocalBlockPosition::unchecked_new(
+            self.x.rem_euclid(CHUNK_WIDTH as i64) as usize,
+            self.y.rem_euclid(CHUNK_HEIGHT as i64) as usize,
+            self.z.rem_euclid(CHUNK_LENGTH as i64) as usize,
+        )
+    }
+
+    pub fn split(&self) -> (ChunkPosition, LocalBlockPosition) {
+        (self.chunk(), self.local())
+    }
+}
+
+/// Number of words in a `ChunkMask`, each word holds one row of blocks along the z axis.
+pub const MASK_WORDS: usize = CHUNK_HEIGHT * CHUNK_WIDTH;
+
+// A row of a `ChunkMask` has to fit a `u64` exactly.
+const _: () = assert!(CHUNK_LENGTH == 64, "ChunkMask assumes chunks are 64 blocks long");
+
+/// One bit per block of a chunk, stored in the same yxz order as the blocks.
+///
+/// Since a chunk is 64 blocks long on the z axis, every (y, x) row is exactly one `u64` with bit
+/// `z` set for the block at that z.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ChunkMask {
+    words: Box<[u64]>,
+}
+
+impl ChunkMask {
+    pub fn empty() -> ChunkMask {
+        ChunkMask {
+            words: vec![0; MASK_WORDS].into_boxed_slice(),
+        }
+    }
+
+    #[inline]
+    fn word_index(x: usize, y: usize) -> usize {
+        y * CHUNK_WIDTH + x
+    }
+
+    #[inline]
+    pub fn get(&self, position: &LocalBlockPosition) -> bool {
+        self.words[ChunkMask::word_index(position.x, position.y)] & (1 << position.z) != 0
+    }
+
+    #[inline]
+    pub fn set(&mut self, position: &LocalBlockPosition, value: bool) {
+        let word = &mut self.words[ChunkMask::word_index(position.x, position.y)];
+        if value {
+            *word |= 1 << position.z;
+        } else {
+            *word &= !(1 << position.z);
+        }
+    }
+
+    /// Bits of the row at (x, y), bit `z` being the block at z.
+    #[inline]
+    pub fn row(&self, x: usize, y: usize) -> u64 {
+        self.words[ChunkMask::word_index(x, y)]
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    pub fn iter(&self) -> ChunkMaskIter<'_> {
+        ChunkMaskIter {
+            words: &self.words,
+            index: 0,
+            current: self.words[0],
+        }
+    }
+}
+
+impl std::fmt::Debug for ChunkMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkMask").field("count", &self.count()).finish()
+    }
+}
+
+/// Positions of the set bits in a `ChunkMask`, in yxz order.
+pub struct ChunkMaskIter<'a> {
+    words: &'a [u64],
+    index: usize,
+    current: u64,
+}
+
+impl<'a> Iterator for ChunkMaskIter<'a> {
+    type Item = LocalBlockPosition;
+
+    fn next(&mut self) -> Option<LocalBlockPosition> {
+        while self.current == 0 {
+            self.index += 1;
+            if self.index >= self.words.len() {
+                return None;
+            }
+            self.current = self.words[self.index];
+        }
+
+        let z = self.current.trailing_zeros() as usize;
+        self.current &= self.current - 1;
+        Some(LocalBlockPosition::unchecked_new(self.index % CHUNK_WIDTH, self.index / CHUNK_WIDTH, z))
+    }
+}
+
 pub trait Chunk {
     fn block(&self, position: &LocalBlockPosition) -> Block;
-    fn visible_blocks<'a>(&self, registry: &'a BlockRegistry) -> Vec<(LocalBlockPosition, &'a BlockDeclaration)> {
-        let mut visible = Vec::new();
 
+    /// Masks of the blocks that are visible and of the blocks that are fully opaque.
+    ///
+    /// Blocks without a declaration are treated as air.
+    fn opacity_masks(&self, registry: &BlockRegistry) -> (ChunkMask, ChunkMask) {
+        let mut visible = ChunkMask::empty();
+        let mut opaque = ChunkMask::empty();
+
+        // Chunks are mostly long runs of the same block, so remember the last lookup.
+        let mut last: Option<(Block, u64, u64)> = None;
         for y in 0..CHUNK_HEIGHT {
             for x in 0..CHUNK_WIDTH {
+                let mut visible_row = 0;
+                let mut opaque_row = 0;
                 for z in 0..CHUNK_LENGTH {
-                    let position = LocalBlockPosition::unchecked_new(x, y, z);
-                    let block = self.block(&position);
-                    let declaration = registry.declaration(block).as_ref().unwrap();
-
-                    if declaration.visible() {
-                        if position.x == 0
-                            || position.y == 0
-                            || position.z == 0
-                            || position.x == CHUNK_WIDTH - 1
-                            || position.y == CHUNK_HEIGHT - 1
-                            || position.z == CHUNK_LENGTH - 1
-                        {
-                            visible.push((position, declaration));
-                            continue
+                    let block = self.block(&LocalBlockPosition::unchecked_new(x, y, z));
+                    let (visible_bit, opaque_bit) = match last {
+                        Some((last_block, visible_bit, opaque_bit)) if last_block == block => (visible_bit, opaque_bit),
+                        _ => {
+                            let bits = match registry.declaration(block) {
+                                Some(declaration) => (declaration.visible() as u64, declaration.opaque() as u64),
+                                None => (0, 0),
+                            };
+                            last = Some((block, bits.0, bits.1));
+                            bits
                         }
+                    };
 
-                        // Check z axis first because of cache locality.
-                        let mut total_transparency: usize = 0;
-                        let check_block = self.block(&LocalBlockPosition::unchecked_new(x, y, z - 1));
-                        let check_declaration = registry.declaration(check_block);
-                        total_transparency += check_declaration.as_ref().unwrap().transparency() as usize;
-                        let check_block = self.block(&LocalBlockPosition::unchecked_new(x, y, z + 1));
-                        let check_declaration = registry.declaration(check_block);
-                        total_transparency += check_declaration.as_ref().unwrap().transparency() as usize;
-
-                        if total_transparency > 0 {
-                            visible.push((position, declaration));
-                            continue;
-                        }
+                    visible_row |= visible_bit << z;
+                    opaque_row |= opaque_bit << z;
+                }
 
-                        let check_block = self.block(&LocalBlockPosition::unchecked_new(x - 1, y, z));
-                        let check_declaration = registry.declaration(check_block);
-                        total_transparency += check_declaration.as_ref().unwrap().transparency() as usize;
+                let index = ChunkMask::word_index(x, y);
+                visible.words[index] = visible_row;
+                opaque.words[index] = opaque_row;
+            }
+        }
 
-                        let check_block = self.block(&LocalBlockPosition::unchecked_new(x + 1, y, z));
-                        let check_declaration = registry.declaration(check_block);
-                        total_transparency += check_declaration.as_ref().unwrap().transparency() as usize;
-                        if total_transparency > 0 {
-                            visible.push((position, declaration));
-                            continue;
-                        }
+        (visible, opaque)
+    }
 
-                        let check_block = self.block(&LocalBlockPosition::unchecked_new(x, y - 1, z));
-                        let check_declaration = registry.declaration(check_block);
-                        total_transparency += check_declaration.as_ref().unwrap().transparency() as usize;
+    /// Mask of the visible blocks that border at least one block that isn't opaque.
+    ///
+    /// Blocks on the chunk border are always visible since the neighbouring chunk is unknown.
+    fn visible_mask(&self, registry: &BlockRegistry) -> ChunkMask {
+        let (visible, opaque) = self.opacity_masks(registry);
+        let mut mask = ChunkMask::empty();
 
-                        let check_block = self.block(&LocalBlockPosition::unchecked_new(x, y + 1, z));
-                        let check_declaration = registry.declaration(check_block);
-                        total_transparency += check_declaration.as_ref().unwrap().transparency() as usize;
-                        if total_transparency > 0 {
-                            visible.push((position, declaration));
-                            continue;
-                        }
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                let row = opaque.row(x, y);
+
+                // Shifting in zeroes leaves the z borders exposed, same for the missing rows at
+                // the x and y borders.
+                let mut hidden = (row << 1) & (row >> 1);
+                hidden &= if x > 0 { opaque.row(x - 1, y) } else { 0 };
+                hidden &= if x + 1 < CHUNK_WIDTH { opaque.row(x + 1, y) } else { 0 };
+                hidden &= if y > 0 { opaque.row(x, y - 1) } else { 0 };
+                hidden &= if y + 1 < CHUNK_HEIGHT { opaque.row(x, y + 1) } else { 0 };
+
+                mask.words[ChunkMask::word_index(x, y)] = visible.row(x, y) & !hidden;
+            }
+        }
+
+        mask
+    }
+
+    fn visible_blocks<'a>(&self, registry: &'a BlockRegistry) -> Vec<(LocalBlockPosition, &'a BlockDeclaration)> {
+        self.visible_mask(registry)
+            .iter()
+            .filter_map(|position| {
+                registry
+                    .declaration(self.block(&position))
+                    .as_ref()
+                    .map(|declaration| (position, declaration))
+            })
+            .collect()
+    }
+}
+
+/// How blocks in a neighbouring chunk that is not available should be treated.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MissingNeighbor {
+    /// Hide faces towards the missing chunk, useful while the world is still loading.
+    Solid,
+    /// Show faces towards the missing chunk, e.g. at the edge of the world.
+    Air,
+}
+
+/// A chunk along with the six chunks sharing a side with it.
+///
+/// Neighbours are indexed in the same order as `Face::ALL`, so blocks on the border of the center
+/// chunk can be checked against the blocks on the other side of it.
+pub struct ChunkNeighborhood<'a> {
+    center: &'a dyn Chunk,
+    neighbors: [Option<&'a dyn Chunk>; 6],
+    missing: MissingNeighbor,
+}
+
+impl<'a> ChunkNeighborhood<'a> {
+    pub fn new(center: &'a dyn Chunk, missing: MissingNeighbor) -> ChunkNeighborhood<'a> {
+        ChunkNeighborhood {
+            center,
+            neighbors: [None; 6],
+            missing,
+        }
+    }
+
+    pub fn with_neighbor(mut self, face: Face, neighbor: &'a dyn Chunk) -> ChunkNeighborhood<'a> {
+        self.set_neighbor(face, Some(neighbor));
+        self
+    }
+
+    pub fn set_neighbor(&mut self, face: Face, neighbor: Option<&'a dyn Chunk>) {
+        self.neighbors[face.index()] = neighbor;
+    }
+
+    pub fn center(&self) -> &'a dyn Chunk {
+        self.center
+    }
+
+    pub fn neighbor(&self, face: Face) -> Option<&'a dyn Chunk> {
+        self.neighbors[face.index()]
+    }
+
+    pub fn missing(&self) -> MissingNeighbor {
+        self.missing
+    }
+
+    /// Block next to `position` in the direction of `face`, which may be in a neighbouring chunk.
+    ///
+    /// `None` if the block is in a neighbouring chunk that is missing.
+    pub fn adjacent_block(&self, position: &LocalBlockPosition, face: Face) -> Option<Block> {
+        if let Some(adjacent) = face.neighbor(position) {
+            return Some(self.center.block(&adjacent));
+        }
+
+        // Wrap around to the opposite side of the neighbouring chunk.
+        let (x, y, z) = face.offset();
+        let wrapped = LocalBlockPosition::unchecked_new(
+            (position.x as isize + x).rem_euclid(CHUNK_WIDTH as isize) as usize,
+            (position.y as isize + y).rem_euclid(CHUNK_HEIGHT as isize) as usize,
+            (position.z as isize + z).rem_euclid(CHUNK_LENGTH as isize) as usize,
+        );
+        self.neighbor(face).map(|neighbor| neighbor.block(&wrapped))
+    }
+
+    /// Whether the face of `position` pointing towards `face` can be seen through its neighbour.
+    pub fn face_exposed(&self, registry: &BlockRegistry, position: &LocalBlockPosition, face: Face) -> bool {
+        match self.adjacent_block(position, face) {
+            Some(block) => match registry.declaration(block) {
+                Some(declaration) => !declaration.opaque(),
+                None => true,
+            },
+            None => self.missing == MissingNeighbor::Air,
+        }
+    }
+
+    /// Same as `Chunk::visible_blocks`, but blocks on the chunk border are checked against the
+    /// neighbouring chunks instead of always being visible.
+    pub fn visible_blocks<'b>(&self, registry: &'b BlockRegistry) -> Vec<(LocalBlockPosition, &'b BlockDeclaration)> {
+        let mut visible = Vec::new();
+
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let position = LocalBlockPosition::unchecked_new(x, y, z);
+                    let declaration = match registry.declaration(self.center.block(&position)) {
+                        Some(declaration) if declaration.visible() => declaration,
+                        _ => continue,
+                    };
+
+                    if Face::ALL.iter().any(|face| self.face_exposed(registry, &position, *face)) {
+                        visible.push((position, declaration));
                     }
                 }
             }
@@ -163,15 +506,21 @@ pub trait ChunkMut {
     fn set_block(&mut self, position: &LocalBlockPosition, block: Block);
 }
 
+/// Chunk that stores every block id in a flat array.
+///
+/// States are kept in a second array that is only allocated once a block with a state other than
+/// 0 is set, so chunks of stateless blocks take `size_of::<BlockSize>()` bytes per block.
 #[derive(Debug)]
 pub struct BoxedChunk {
-    blocks: Box<[Block]>,
+    ids: Box<[BlockSize]>,
+    states: Option<Box<[BlockState]>>,
 }
 
 impl BoxedChunk {
     pub fn empty() -> BoxedChunk {
         BoxedChunk {
-            blocks: vec![EMPTY_BLOCK; CHUNK_SIZE as usize].into_boxed_slice(),
+            ids: vec![EMPTY_BLOCK.id(); CHUNK_SIZE as usize].into_boxed_slice(),
+            states: None,
         }
     }
 
@@ -188,31 +537,56 @@ impl BoxedChunk {
 
     pub fn get_ref(&self) -> ChunkRef<'_> {
         ChunkRef {
-            blocks: &self.blocks,
+            ids: &self.ids,
+            states: self.states.as_deref(),
         }
     }
+
+    /// Approximate heap memory used by the chunk, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.ids.len() * std::mem::size_of::<BlockSize>()
+            + self.states.as_ref().map_or(0, |states| states.len() * std::mem::size_of::<BlockState>())
+    }
+}
+
+impl Default for BoxedChunk {
+    fn default() -> BoxedChunk {
+        BoxedChunk::empty()
+    }
 }
 
 impl Chunk for BoxedChunk {
     fn block(&self, position: &LocalBlockPosition) -> Block {
-        self.blocks[position.index() as usize]
+        self.get_ref().block(position)
     }
 }
 
 impl ChunkMut for BoxedChunk {
     fn set_block(&mut self, position: &LocalBlockPosition, block: Block) {
-        self.blocks[position.index() as usize] = block;
+        let index = position.index() as usize;
+        self.ids[index] = block.id();
+        match &mut self.states {
+            Some(states) => states[index] = block.state(),
+            None if block.state() != 0 => {
+                let mut states = vec![0; CHUNK_SIZE].into_boxed_slice();
+                states[index] = block.state();
+                self.states = Some(states);
+            },
+            None => {},
+        }
     }
 }
 
 pub struct ChunkRef<'a> {
     // Stored in yxz order for caching (we will probably hit horizontal axis together).
-    blocks: &'a [Block],
+    ids: &'a [BlockSize],
+    states: Option<&'a [BlockState]>,
 }
 
 impl<'a> Chunk for ChunkRef<'a> {
     fn block(&self, position: &LocalBlockPosition) -> Block {
-        self.blocks[position.index() as usize]
+        let index = position.index() as usize;
+        Block::with_state(self.ids[index], self.states.map_or(0, |states| states[index]))
     }
 }
 
@@ -242,14 +616,167 @@ impl<'a> ChunkRef<'a> {
             //.map(|(y, x, z)| LocalBlockPosition { y, x, z })
     //}
 
-    //pub fn mesh(&self) -> ChunkMesh {
-    //}
+    pub fn mesh(&self, registry: &BlockRegistry, mode: MeshMode) -> ChunkMesh {
+        ChunkMesh::build(self, registry, mode)
+    }
+}
+
+const PALETTE_WORD_BITS: usize = 64;
+
+#[inline]
+fn pack_index(indices: &mut [u64], bits: usize, index: usize, value: usize) {
+    let bit = index * bits;
+    let offset = bit % PALETTE_WORD_BITS;
+    let mask = ((1u64 << bits) - 1) << offset;
+    let word = &mut indices[bit / PALETTE_WORD_BITS];
+    *word = (*word & !mask) | (((value as u64) << offset) & mask);
+}
+
+/// Chunk that stores a palette of the distinct blocks it contains and a bit-packed palette index
+/// per position.
+///
+/// Index widths are kept to powers of two so an index never straddles a word, and grow as new
+/// blocks are added to the palette. A chunk made of a single block type stores no indices at all.
+#[derive(Debug, Clone)]
+pub struct PalettedChunk {
+    palette: Vec<Block>,
+    lookup: HashMap<Block, usize>,
+    bits: usize,
+    indices: Box<[u64]>,
+}
+
+impl PalettedChunk {
+    pub fn empty() -> PalettedChunk {
+        PalettedChunk::filled(EMPTY_BLOCK)
+    }
+
+    pub fn filled(block: Block) -> PalettedChunk {
+        let mut lookup = HashMap::new();
+        lookup.insert(block, 0);
+
+        PalettedChunk {
+            palette: vec![block],
+            lookup,
+            bits: 0,
+            indices: Box::new([]),
+        }
+    }
+
+    pub fn flat(block: Block, y: usize) -> PalettedChunk {
+        let mut chunk = PalettedChunk::empty();
+        for x in 0..(CHUNK_WIDTH - 1) {
+            for z in 0..(CHUNK_LENGTH - 1) {
+                let position = LocalBlockPosition::unchecked_new(x, y, z);
+                chunk.set_block(&position, block);
+            }
+        }
+        chunk
+    }
+
+    pub fn from_chunk<C: Chunk>(chunk: &C) -> PalettedChunk {
+        let mut paletted = PalettedChunk::empty();
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let position = LocalBlockPosition::unchecked_new(x, y, z);
+                    paletted.set_block(&position, chunk.block(&position));
+                }
+            }
+        }
+        paletted
+    }
+
+    /// Distinct blocks this chunk has stored, in the order they were first added.
+    ///
+    /// Blocks that have since been overwritten are not removed from the palette.
+    pub fn palette(&self) -> &[Block] {
+        &self.palette
+    }
+
+    pub fn bits_per_block(&self) -> usize {
+        self.bits
+    }
+
+    /// Approximate heap memory used by the palette and indices in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.palette.capacity() * std::mem::size_of::<Block>()
+            + self.lookup.capacity() * std::mem::size_of::<(Block, usize)>()
+            + self.indices.len() * std::mem::size_of::<u64>()
+    }
+
+    #[inline]
+    fn palette_index(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+
+        let bit = index * self.bits;
+        let word = self.indices[bit / PALETTE_WORD_BITS];
+        let mask = (1u64 << self.bits) - 1;
+        ((word >> (bit % PALETTE_WORD_BITS)) & mask) as usize
+    }
+
+    #[inline]
+    fn set_palette_index(&mut self, index: usize, value: usize) {
+        pack_index(&mut self.indices, self.bits, index, value);
+    }
+
+    // Doubles the index width and repacks every index.
+    fn grow(&mut self) {
+        let bits = if self.bits == 0 { 1 } else { self.bits * 2 };
+        let mut indices = vec![0u64; CHUNK_SIZE * bits / PALETTE_WORD_BITS].into_boxed_slice();
+
+        for index in 0..CHUNK_SIZE {
+            let value = self.palette_index(index);
+            if value != 0 {
+                pack_index(&mut indices, bits, index, value);
+            }
+        }
+
+        self.bits = bits;
+        self.indices = indices;
+    }
+}
+
+impl Default for PalettedChunk {
+    fn default() -> PalettedChunk {
+        PalettedChunk::empty()
+    }
+}
+
+impl Chunk for PalettedChunk {
+    fn block(&self, position: &LocalBlockPosition) -> Block {
+        self.palette[self.palette_index(position.index())]
+    }
+}
+
+impl ChunkMut for PalettedChunk {
+    fn set_block(&mut self, position: &LocalBlockPosition, block: Block) {
+        let value = match self.lookup.get(&block) {
+            Some(value) => *value,
+            None => {
+                if self.palette.len() >= 1 << self.bits {
+                    self.grow();
+                }
+
+                let value = self.palette.len();
+                self.palette.push(block);
+                self.lookup.insert(block, value);
+                value
+            }
+        };
+
+        if self.bits > 0 {
+            self.set_palette_index(position.index(), value);
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::chunk::{Chunk, ChunkMut, BoxedChunk, ChunkRef, LocalBlockPosition, CHUNK_HEIGHT, CHUNK_WIDTH, CHUNK_LENGTH, CHUNK_SIZE, Y_SIZE, X_SIZE, Z_SIZE};
-    use crate::block::{Block, BlockSize, MAX_BLOCK_ID, BlockRegistry, BlockDeclaration};
+    use crate::chunk::{Chunk, ChunkMut, BoxedChunk, ChunkRef, PalettedChunk, ChunkMask, ChunkPosition, WorldBlockPosition, ChunkNeighborhood, MissingNeighbor, LocalBlockPosition, CHUNK_HEIGHT, CHUNK_WIDTH, CHUNK_LENGTH, CHUNK_SIZE, Y_SIZE, X_SIZE, Z_SIZE};
+    use crate::block::{Block, BlockSize, BlockState, MAX_BLOCK_ID, EMPTY_BLOCK, BlockRegistry, BlockDeclaration};
+    use crate::mesh::Face;
     use std::collections::HashSet;
 
     // Sanity checking that setting and getting blocks refer to the same position.
@@ -312,6 +839,211 @@ mod test {
         println!("{:?}", visible);
     }
 
+    #[test]
+    fn world_position_conversion() {
+        let position = WorldBlockPosition::new(-1, 64, -65);
+        let (chunk, local) = position.split();
+        assert_eq!(chunk, ChunkPosition::new(-1, 1, -2));
+        assert_eq!((local.x(), local.y(), local.z()), (CHUNK_WIDTH - 1, 0, CHUNK_LENGTH - 1));
+        assert_eq!(WorldBlockPosition::from_local(chunk, &local), position);
+
+        for &coordinate in &[-130i64, -129, -128, -65, -64, -63, -1, 0, 1, 63, 64, 65, 128] {
+            let position = WorldBlockPosition::new(coordinate, coordinate, coordinate);
+            let (chunk, local) = position.split();
+            assert_eq!(WorldBlockPosition::from_local(chunk, &local), position);
+            assert!(LocalBlockPosition::new(local.x(), local.y(), local.z()).is_some());
+        }
+
+        assert_eq!(ChunkPosition::new(-2, 0, 3).origin(), WorldBlockPosition::new(-128, 0, 192));
+
+        // The far edges of the world still round trip.
+        for &chunk in &[ChunkPosition::new(i32::MIN, 0, 0), ChunkPosition::new(0, i32::MAX, 0)] {
+            let far = WorldBlockPosition::from_local(chunk, &LocalBlockPosition::unchecked_new(CHUNK_WIDTH - 1, 0, 0));
+            assert_eq!(far.chunk(), chunk);
+        }
+    }
+
+    #[test]
+    fn checked_positions() {
+        assert_eq!(WorldBlockPosition::new(i64::MAX, 0, 0).checked_chunk(), None);
+        assert_eq!(WorldBlockPosition::new(-65, 0, 64).checked_chunk(), Some(ChunkPosition::new(-2, 0, 1)));
+        assert_eq!(ChunkPosition::new(0, i32::MIN, 0).checked_offset(0, -1, 0), None);
+        assert_eq!(ChunkPosition::new(0, i32::MIN, 0).checked_offset(0, 1, 0), Some(ChunkPosition::new(0, i32::MIN + 1, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk position out of range")]
+    fn world_position_overflow() {
+        WorldBlockPosition::new(i64::MAX, 0, 0).chunk();
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk position out of range")]
+    fn chunk_offset_overflow() {
+        ChunkPosition::new(i32::MAX, 0, 0).offset(1, 0, 0);
+    }
+
+    #[test]
+    fn neighborhood_visible() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let dirt = PalettedChunk::filled(Block::hard_create(1));
+        let air = PalettedChunk::empty();
+
+        // Buried chunk borders are culled when every neighbour is solid.
+        let mut neighborhood = ChunkNeighborhood::new(&dirt, MissingNeighbor::Air);
+        for face in Face::ALL.iter() {
+            neighborhood.set_neighbor(*face, Some(&dirt));
+        }
+        assert_eq!(neighborhood.visible_blocks(&registry).len(), 0);
+
+        // Only the side facing the air chunk is exposed.
+        neighborhood.set_neighbor(Face::PosX, Some(&air));
+        let visible = neighborhood.visible_blocks(&registry);
+        assert_eq!(visible.len(), CHUNK_HEIGHT * CHUNK_LENGTH);
+        assert!(visible.iter().all(|(position, _)| position.x() == CHUNK_WIDTH - 1));
+
+        let solid = ChunkNeighborhood::new(&dirt, MissingNeighbor::Solid);
+        assert_eq!(solid.visible_blocks(&registry).len(), 0);
+
+        let open = ChunkNeighborhood::new(&dirt, MissingNeighbor::Air);
+        let inner = (CHUNK_WIDTH - 2) * (CHUNK_HEIGHT - 2) * (CHUNK_LENGTH - 2);
+        assert_eq!(open.visible_blocks(&registry).len(), CHUNK_SIZE - inner);
+        assert_eq!(open.visible_blocks(&registry).len(), dirt.visible_blocks(&registry).len());
+    }
+
+    #[test]
+    fn mask_iteration() {
+        let mut mask = ChunkMask::empty();
+        let positions = [(0, 0, 0), (0, 0, 63), (5, 0, 1), (63, 7, 32), (63, 63, 63)];
+        for &(x, y, z) in positions.iter() {
+            mask.set(&LocalBlockPosition::unchecked_new(x, y, z), true);
+        }
+
+        let iterated: Vec<_> = mask.iter().map(|position| (position.x(), position.y(), position.z())).collect();
+        assert_eq!(iterated, positions.to_vec());
+        assert_eq!(mask.count(), positions.len());
+
+        mask.set(&LocalBlockPosition::unchecked_new(5, 0, 1), false);
+        assert!(!mask.get(&LocalBlockPosition::unchecked_new(5, 0, 1)));
+        assert_eq!(mask.count(), positions.len() - 1);
+    }
+
+    #[test]
+    fn visible_mask_matches_neighborhood() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let mut chunk = BoxedChunk::flat(Block::hard_create(1), 5);
+        for x in 0..CHUNK_WIDTH {
+            for y in 0..(x % 13) {
+                for z in (x % 3)..(CHUNK_LENGTH - x % 7) {
+                    chunk.set_block(&LocalBlockPosition::unchecked_new(x, y, z), Block::hard_create(1));
+                }
+            }
+        }
+
+        // Without any neighbours and missing ones treated as air every border block is visible,
+        // the same as the mask.
+        let expected: Vec<usize> = ChunkNeighborhood::new(&chunk, MissingNeighbor::Air)
+            .visible_blocks(&registry)
+            .iter()
+            .map(|(position, _)| position.index())
+            .collect();
+        let mask: Vec<usize> = chunk.visible_mask(&registry).iter().map(|position| position.index()).collect();
+        assert_eq!(mask, expected);
+
+        let blocks: Vec<usize> = chunk.visible_blocks(&registry).iter().map(|(position, _)| position.index()).collect();
+        assert_eq!(blocks, expected);
+    }
+
+    #[test]
+    fn paletted_block_position() {
+        let mut chunk = PalettedChunk::empty();
+        let mut boxed = BoxedChunk::empty();
+
+        let mut current: usize = 0;
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let position = LocalBlockPosition::new(x, y, z).unwrap();
+
+                    // 300 distinct blocks even with 8 bit ids.
+                    let created_block = Block::with_state((current % 256) as BlockSize, (current / 256) as BlockState);
+                    chunk.set_block(&position, created_block);
+                    boxed.set_block(&position, created_block);
+                    assert_eq!(created_block, chunk.block(&position));
+                    current = (current + 1) % 300;
+                }
+            }
+        }
+
+        // Growing the index width must not disturb blocks that were already set.
+        assert_eq!(chunk.palette().len(), 300);
+        assert_eq!(chunk.bits_per_block(), 16);
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let position = LocalBlockPosition::unchecked_new(x, y, z);
+                    assert_eq!(boxed.block(&position), chunk.block(&position));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn paletted_bit_growth() {
+        let mut chunk = PalettedChunk::empty();
+        assert_eq!(chunk.bits_per_block(), 0);
+
+        let position = LocalBlockPosition::unchecked_new(1, 2, 3);
+        chunk.set_block(&position, Block::hard_create(1));
+        assert_eq!(chunk.bits_per_block(), 1);
+
+        chunk.set_block(&position, Block::hard_create(2));
+        assert_eq!(chunk.bits_per_block(), 2);
+
+        chunk.set_block(&position, Block::hard_create(3));
+        chunk.set_block(&position, Block::hard_create(4));
+        assert_eq!(chunk.bits_per_block(), 4);
+
+        assert_eq!(chunk.block(&position), Block::hard_create(4));
+        assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(0, 0, 0)), EMPTY_BLOCK);
+    }
+
+    #[test]
+    fn paletted_memory() {
+        let chunk = PalettedChunk::flat(Block::hard_create(1), 5);
+        let boxed_size = BoxedChunk::flat(Block::hard_create(1), 5).memory_usage();
+        // Two blocks take a single bit each, against a whole id per block in a `BoxedChunk`.
+        let ratio = 8 * std::mem::size_of::<BlockSize>() - 1;
+        assert!(chunk.memory_usage() < boxed_size / ratio, "{} bytes", chunk.memory_usage());
+    }
+
+    #[test]
+    fn boxed_states() {
+        let mut chunk = BoxedChunk::flat(Block::hard_create(1), 5);
+        assert_eq!(chunk.memory_usage(), CHUNK_SIZE * std::mem::size_of::<BlockSize>());
+
+        let position = LocalBlockPosition::unchecked_new(3, 4, 5);
+        chunk.set_block(&position, Block::with_state(2, 7));
+        assert_eq!(chunk.memory_usage(), CHUNK_SIZE * (std::mem::size_of::<BlockSize>() + std::mem::size_of::<BlockState>()));
+        assert_eq!(chunk.block(&position), Block::with_state(2, 7));
+        assert_eq!(chunk.get_ref().block(&position), Block::with_state(2, 7));
+        assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(0, 5, 0)), Block::hard_create(1));
+
+        chunk.set_block(&position, Block::hard_create(2));
+        assert_eq!(chunk.block(&position), Block::hard_create(2));
+    }
+
+    #[test]
+    fn paletted_visible() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let boxed = BoxedChunk::flat(Block::hard_create(1), 5);
+        let paletted = PalettedChunk::from_chunk(&boxed);
+
+        let boxed_visible: Vec<usize> = boxed.visible_blocks(&registry).iter().map(|(position, _)| position.index()).collect();
+        let paletted_visible: Vec<usize> = paletted.visible_blocks(&registry).iter().map(|(position, _)| position.index()).collect();
+        assert_eq!(boxed_visible, paletted_visible);
+    }
+
     //#[test]
     //fn surrounding_positions() {
         //struct SurroundingCoverage {
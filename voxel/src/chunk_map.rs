@@ -0,0 +1,237 @@
+//! Chunk storage that can be shared between threads without a global lock.
+//!
+//! Positions are spread over a fixed number of buckets, each holding an immutable list of
+//! `(ChunkPosition, Arc<C>)` pairs behind an `ArcSwap`. Readers load a bucket without blocking
+//! and get an `Arc` snapshot of the chunk, which stays valid no matter what writers do afterwards.
+//! Writers copy the (small) bucket, modify the copy and compare-and-swap it back in, retrying if
+//! another writer got there first.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::block::Block;
+use crate::chunk::{Chunk, BoxedChunk, ChunkPosition, WorldBlockPosition};
+
+pub const DEFAULT_BUCKETS: usize = 1024;
+
+type Bucket<C> = Vec<(ChunkPosition, Arc<C>)>;
+
+pub struct ChunkMap<C = BoxedChunk> {
+    buckets: Box<[ArcSwap<Bucket<C>>]>,
+}
+
+impl<C> ChunkMap<C> {
+    pub fn new() -> ChunkMap<C> {
+        ChunkMap::with_buckets(DEFAULT_BUCKETS)
+    }
+
+    pub fn with_buckets(count: usize) -> ChunkMap<C> {
+        let buckets: Vec<_> = (0..count.max(1))
+            .map(|_| ArcSwap::from_pointee(Vec::new()))
+            .collect();
+
+        ChunkMap {
+            buckets: buckets.into_boxed_slice(),
+        }
+    }
+
+    fn bucket(&self, position: &ChunkPosition) -> &ArcSwap<Bucket<C>> {
+        let mut hasher = DefaultHasher::new();
+        position.hash(&mut hasher);
+        &self.buckets[hasher.finish() as usize % self.buckets.len()]
+    }
+
+    /// Snapshot of the chunk currently stored at `position`.
+    pub fn get(&self, position: &ChunkPosition) -> Option<Arc<C>> {
+        self.bucket(position)
+            .load()
+            .iter()
+            .find(|(stored, _)| stored == position)
+            .map(|(_, chunk)| chunk.clone())
+    }
+
+    pub fn contains(&self, position: &ChunkPosition) -> bool {
+        self.bucket(position)
+            .load()
+            .iter()
+            .any(|(stored, _)| stored == position)
+    }
+
+    /// Store a new version of the chunk at `position`, returning the version it replaced.
+    pub fn insert(&self, position: ChunkPosition, chunk: C) -> Option<Arc<C>> {
+        self.insert_arc(position, Arc::new(chunk))
+    }
+
+    pub fn insert_arc(&self, position: ChunkPosition, chunk: Arc<C>) -> Option<Arc<C>> {
+        let previous = self.bucket(&position).rcu(|bucket| {
+            let mut next: Bucket<C> = bucket
+                .iter()
+                .filter(|(stored, _)| *stored != position)
+                .cloned()
+                .collect();
+            next.push((position, chunk.clone()));
+            next
+        });
+
+        find(&previous, &position)
+    }
+
+    pub fn remove(&self, position: &ChunkPosition) -> Option<Arc<C>> {
+        let previous = self.bucket(position).rcu(|bucket| {
+            bucket
+                .iter()
+                .filter(|(stored, _)| stored != position)
+                .cloned()
+                .collect::<Bucket<C>>()
+        });
+
+        find(&previous, position)
+    }
+
+    /// Replace the chunk at `position` with a new version derived from the current one.
+    ///
+    /// `f` can be called more than once if other writers touch the same bucket concurrently, only
+    /// the result computed from the version that was actually replaced is stored. Returns the new
+    /// version, or `None` if there was no chunk at `position`.
+    pub fn update<F: FnMut(&C) -> C>(&self, position: &ChunkPosition, mut f: F) -> Option<Arc<C>> {
+        let mut updated = None;
+        self.bucket(position).rcu(|bucket| {
+            let mut next = Bucket::clone(bucket);
+            updated = None;
+            if let Some((_, chunk)) = next.iter_mut().find(|(stored, _)| stored == position) {
+                *chunk = Arc::new(f(chunk));
+                updated = Some(chunk.clone());
+            }
+            next
+        });
+
+        updated
+    }
+
+    /// Number of chunks stored, which may already be outdated if other threads are writing.
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.load().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(|bucket| bucket.load().is_empty())
+    }
+
+    pub fn positions(&self) -> Vec<ChunkPosition> {
+        self.buckets
+            .iter()
+            .flat_map(|bucket| bucket.load().iter().map(|(position, _)| *position).collect::<Vec<_>>())
+            .collect()
+    }
+}
+
+impl<C: Chunk> ChunkMap<C> {
+    /// Block at a world position, `None` if the chunk containing it is not stored or can't exist.
+    pub fn block(&self, position: &WorldBlockPosition) -> Option<Block> {
+        let chunk = position.checked_chunk()?;
+        self.get(&chunk).map(|chunk| chunk.block(&position.local()))
+    }
+}
+
+impl<C> Default for ChunkMap<C> {
+    fn default() -> ChunkMap<C> {
+        ChunkMap::new()
+    }
+}
+
+fn find<C>(bucket: &Bucket<C>, position: &ChunkPosition) -> Option<Arc<C>> {
+    bucket
+        .iter()
+        .find(|(stored, _)| stored == position)
+        .map(|(_, chunk)| chunk.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::chunk_map::ChunkMap;
+    use crate::chunk::{Chunk, PalettedChunk, ChunkPosition, LocalBlockPosition, CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH};
+    use crate::block::{Block, BlockSize};
+
+    #[test]
+    fn insert_get_remove() {
+        let map: ChunkMap<PalettedChunk> = ChunkMap::with_buckets(4);
+        let position = ChunkPosition::new(-3, 1, 7);
+
+        assert!(map.insert(position, PalettedChunk::filled(Block::hard_create(1))).is_none());
+        let snapshot = map.get(&position).unwrap();
+
+        let previous = map.insert(position, PalettedChunk::filled(Block::hard_create(2))).unwrap();
+        assert!(Arc::ptr_eq(&previous, &snapshot));
+
+        // Old snapshots are unaffected by newer versions.
+        let origin = LocalBlockPosition::unchecked_new(0, 0, 0);
+        assert_eq!(snapshot.block(&origin), Block::hard_create(1));
+        assert_eq!(map.get(&position).unwrap().block(&origin), Block::hard_create(2));
+
+        assert_eq!(map.len(), 1);
+        assert!(map.remove(&position).is_some());
+        assert!(map.get(&position).is_none());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn concurrent_stress() {
+        const THREADS: usize = 8;
+        const ITERATIONS: usize = 200;
+
+        // Few buckets so that writers constantly contend with each other.
+        let map: Arc<ChunkMap<PalettedChunk>> = Arc::new(ChunkMap::with_buckets(4));
+        let shared = ChunkPosition::new(0, 0, 0);
+        map.insert(shared, PalettedChunk::filled(Block::hard_create(0)));
+
+        let corner = LocalBlockPosition::unchecked_new(CHUNK_WIDTH - 1, CHUNK_HEIGHT - 1, CHUNK_LENGTH - 1);
+        let origin = LocalBlockPosition::unchecked_new(0, 0, 0);
+
+        let mut handles = Vec::new();
+        for thread in 0..THREADS {
+            let map = map.clone();
+            handles.push(thread::spawn(move || {
+                let own = ChunkPosition::new(thread as i32 + 1, 0, 0);
+                for iteration in 0..ITERATIONS {
+                    // Every update to the shared chunk has to land exactly once.
+                    map.update(&shared, |chunk| {
+                        PalettedChunk::filled(Block::hard_create(chunk.block(&origin).id().wrapping_add(1)))
+                    });
+
+                    map.insert(own, PalettedChunk::filled(Block::hard_create(iteration as BlockSize)));
+                    if iteration % 3 == 0 {
+                        map.remove(&own);
+                    }
+
+                    // Snapshots are never observed half written.
+                    for position in map.positions() {
+                        if let Some(chunk) = map.get(&position) {
+                            assert_eq!(chunk.block(&origin), chunk.block(&corner));
+                        }
+                    }
+                }
+            }));
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Wraps around the same way as the updates with narrow block ids.
+        let total = (THREADS * ITERATIONS) as BlockSize;
+        assert_eq!(map.get(&shared).unwrap().block(&origin), Block::hard_create(total));
+
+        let last = Block::hard_create((ITERATIONS - 1) as BlockSize);
+        for thread in 0..THREADS {
+            let own = ChunkPosition::new(thread as i32 + 1, 0, 0);
+            assert_eq!(map.get(&own).unwrap().block(&origin), last);
+        }
+        assert_eq!(map.len(), THREADS + 1);
+    }
+}
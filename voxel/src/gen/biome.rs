@@ -0,0 +1,405 @@
+//! Biomes, chosen for each column from temperature and humidity noise.
+//!
+//! Every biome is declared with the climate it prefers, and a column belongs to the biome whose
+//! climate is closest to its own. Near the border between biomes their amplitudes are blended so
+//! the terrain doesn't form cliffs, and their blocks are mixed column by column.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::block::registry::deserialize_color;
+use crate::block::{Block, BlockRegistry, RegistryError, RegistryFormat};
+use crate::gen::noise::{hash, mix, FractalNoise};
+use crate::gen::GeneratorError;
+use crate::texture::RgbaImage;
+
+pub const DEFAULT_CLIMATE_FREQUENCY: f64 = 1.0 / 512.0;
+pub const DEFAULT_CLIMATE_OCTAVES: usize = 3;
+pub const DEFAULT_BLEND: f64 = 0.05;
+pub const DEFAULT_SUBSURFACE_DEPTH: i64 = 3;
+
+const TEMPERATURE_SEED: u64 = 0x7465_6d70;
+const HUMIDITY_SEED: u64 = 0x6875_6d69;
+
+fn default_subsurface_depth() -> i64 {
+    DEFAULT_SUBSURFACE_DEPTH
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BiomeDeclaration {
+    /// Name of the topmost block of a column.
+    surface: String,
+    /// Name of the blocks right below the surface.
+    subsurface: String,
+    /// Name of the blocks below the subsurface.
+    filler: String,
+    #[serde(default = "default_subsurface_depth")]
+    subsurface_depth: i64,
+    /// Largest distance of the surface from the base height.
+    amplitude: f64,
+    temperature: f64,
+    humidity: f64,
+    /// Color on exported biome maps.
+    #[serde(deserialize_with = "deserialize_color")]
+    color: (u8, u8, u8),
+}
+
+/// Biome declarations by name, as found in `biomes.json`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct BiomeFile(BTreeMap<String, BiomeDeclaration>);
+
+impl BiomeFile {
+    /// Load a file in the format its extension names, JSON if it has none.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<BiomeFile, RegistryError> {
+        let path = path.as_ref();
+        let format = RegistryFormat::from_path(path).unwrap_or_default();
+        let contents = std::fs::read_to_string(path).map_err(|e| RegistryError::io(e).at(path))?;
+        BiomeFile::from_str(&contents, format).map_err(|e| e.at(path))
+    }
+
+    pub fn from_reader<R: io::Read>(mut reader: R, format: RegistryFormat) -> Result<BiomeFile, RegistryError> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).map_err(RegistryError::io)?;
+        BiomeFile::from_str(&contents, format)
+    }
+
+    pub fn from_str(string: &str, format: RegistryFormat) -> Result<BiomeFile, RegistryError> {
+        format.deserialize(string)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn declaration(&self, name: &str) -> Option<&BiomeDeclaration> {
+        self.0.get(name)
+    }
+}
+
+/// A biome with its blocks looked up in a registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    name: String,
+    surface: Block,
+    subsurface: Block,
+    filler: Block,
+    subsurface_depth: i64,
+    amplitude: f64,
+    temperature: f64,
+    humidity: f64,
+    color: (u8, u8, u8),
+}
+
+impl Biome {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn surface(&self) -> Block {
+        self.surface
+    }
+
+    pub fn subsurface(&self) -> Block {
+        self.subsurface
+    }
+
+    pub fn filler(&self) -> Block {
+        self.filler
+    }
+
+    pub fn subsurface_depth(&self) -> i64 {
+        self.subsurface_depth
+    }
+
+    pub fn amplitude(&self) -> f64 {
+        self.amplitude
+    }
+
+    /// Preferred temperature and humidity, between -1 and 1 like the climate noise.
+    pub fn climate(&self) -> (f64, f64) {
+        (self.temperature, self.humidity)
+    }
+
+    pub fn color(&self) -> (u8, u8, u8) {
+        self.color
+    }
+}
+
+/// The biomes of a world and the climate noise choosing between them.
+#[derive(Debug, Clone)]
+pub struct BiomeMap {
+    biomes: Vec<Biome>,
+    frequency: f64,
+    octaves: usize,
+    blend: f64,
+}
+
+impl BiomeMap {
+    /// Resolve the block names of every biome in `file` through `registry`.
+    pub fn new(file: &BiomeFile, registry: &BlockRegistry) -> Result<BiomeMap, GeneratorError> {
+        if file.is_empty() {
+            return Err(GeneratorError::NoBiomes);
+        }
+
+        let block = |name: &str| registry.block_by_name(name).ok_or_else(|| GeneratorError::MissingBlock(name.to_owned()));
+        let mut biomes = Vec::new();
+        for (name, declaration) in &file.0 {
+            biomes.push(Biome {
+                name: name.clone(),
+                surface: block(&declaration.surface)?,
+                subsurface: block(&declaration.subsurface)?,
+                filler: block(&declaration.filler)?,
+                subsurface_depth: declaration.subsurface_depth,
+                amplitude: declaration.amplitude,
+                temperature: declaration.temperature,
+                humidity: declaration.humidity,
+                color: declaration.color,
+            });
+        }
+
+        Ok(BiomeMap {
+            biomes,
+            frequency: DEFAULT_CLIMATE_FREQUENCY,
+            octaves: DEFAULT_CLIMATE_OCTAVES,
+            blend: DEFAULT_BLEND,
+        })
+    }
+
+    /// Frequency of the climate noise, larger values give smaller biomes.
+    pub fn frequency(mut self, frequency: f64) -> BiomeMap {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn octaves(mut self, octaves: usize) -> BiomeMap {
+        self.octaves = octaves;
+        self
+    }
+
+    /// How much closer in climate the nearest biome has to be than another for the other to have
+    /// no influence on a column, 0 for hard borders.
+    pub fn blend(mut self, blend: f64) -> BiomeMap {
+        self.blend = blend;
+        self
+    }
+
+    /// Biomes sorted by name.
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    pub fn biome(&self, name: &str) -> Option<&Biome> {
+        self.biomes.iter().find(|biome| biome.name == name)
+    }
+
+    /// Sampler of the biomes of the world with `seed`.
+    pub fn sampler(&self, seed: u64) -> BiomeSampler<'_> {
+        BiomeSampler {
+            map: self,
+            seed,
+            temperature: FractalNoise::new(mix(seed ^ TEMPERATURE_SEED), self.octaves, self.frequency),
+            humidity: FractalNoise::new(mix(seed ^ HUMIDITY_SEED), self.octaves, self.frequency),
+        }
+    }
+
+    /// Image of the biomes of `width` by `height` columns starting at world `x`, `z`, with x
+    /// growing to the right and z downwards.
+    pub fn image(&self, seed: u64, x: i64, z: i64, width: u32, height: u32) -> RgbaImage {
+        let sampler = self.sampler(seed);
+        let mut image = RgbaImage::new(width, height);
+        for row in 0..height {
+            for column in 0..width {
+                let (r, g, b) = sampler.biome(x + column as i64, z + row as i64).color;
+                image.set_pixel(column, row, [r, g, b, 255]);
+            }
+        }
+        image
+    }
+}
+
+/// Biomes of a single world, see `BiomeMap::sampler`.
+#[derive(Debug, Clone)]
+pub struct BiomeSampler<'a> {
+    map: &'a BiomeMap,
+    seed: u64,
+    temperature: FractalNoise,
+    humidity: FractalNoise,
+}
+
+impl<'a> BiomeSampler<'a> {
+    /// Temperature and humidity of the column at world `x`, `z`.
+    pub fn climate(&self, x: i64, z: i64) -> (f64, f64) {
+        (self.temperature.get2(x as f64, z as f64), self.humidity.get2(x as f64, z as f64))
+    }
+
+    /// Biome closest to the climate of the column at world `x`, `z`.
+    pub fn biome(&self, x: i64, z: i64) -> &'a Biome {
+        let distances = self.distances(x, z);
+        let nearest = (0..distances.len()).fold(0, |nearest, i| if distances[i] < distances[nearest] { i } else { nearest });
+        &self.map.biomes[nearest]
+    }
+
+    /// Influence of each biome on the column at world `x`, `z`, adding up to 1.
+    ///
+    /// Weights change smoothly with the climate, so anything blended with them is continuous
+    /// across biome borders.
+    pub fn weights(&self, x: i64, z: i64) -> Vec<(&'a Biome, f64)> {
+        let distances = self.distances(x, z);
+        let nearest = distances.iter().cloned().fold(f64::INFINITY, f64::min);
+
+        let mut weights: Vec<(&'a Biome, f64)> = self
+            .map
+            .biomes
+            .iter()
+            .zip(&distances)
+            .map(|(biome, distance)| {
+                let closeness = (self.map.blend - (distance - nearest)).max(0.0);
+                (biome, closeness * closeness)
+            })
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+
+        if weights.is_empty() {
+            // No blending at all.
+            return vec![(self.biome(x, z), 1.0)];
+        }
+
+        let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+        for (_, weight) in &mut weights {
+            *weight /= total;
+        }
+        weights
+    }
+
+    /// Amplitude of the column at world `x`, `z`, blended between the biomes around it.
+    pub fn amplitude(&self, x: i64, z: i64) -> f64 {
+        self.weights(x, z).iter().map(|(biome, weight)| biome.amplitude * weight).sum()
+    }
+
+    /// Biome whose blocks the column at world `x`, `z` is made of.
+    ///
+    /// Near a border this is picked at random in proportion to the weights, so the blocks of
+    /// neighbouring biomes are mixed rather than meeting in a sharp line.
+    pub fn column_biome(&self, x: i64, z: i64) -> &'a Biome {
+        let weights = self.weights(x, z);
+        let mut pick = (hash(self.seed, x, 0, z) >> 11) as f64 / (1u64 << 53) as f64;
+        for (biome, weight) in &weights {
+            if pick < *weight {
+                return biome;
+            }
+            pick -= weight;
+        }
+        weights[weights.len() - 1].0
+    }
+
+    fn distances(&self, x: i64, z: i64) -> Vec<f64> {
+        let (temperature, humidity) = self.climate(x, z);
+        self.map
+            .biomes
+            .iter()
+            .map(|biome| {
+                let (dt, dh) = (temperature - biome.temperature, humidity - biome.humidity);
+                (dt * dt + dh * dh).sqrt()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::{BlockRegistry, RegistryFormat};
+    use crate::chunk::{Chunk, ChunkPosition, LocalBlockPosition};
+    use crate::gen::biome::{BiomeFile, BiomeMap};
+    use crate::gen::{ChunkGenerator, GeneratorError, HeightmapGenerator};
+    use crate::texture::RgbaImage;
+
+    fn biomes() -> (BlockRegistry, BiomeMap) {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let file = BiomeFile::from_file("resources/biomes.json").unwrap();
+        let map = BiomeMap::new(&file, &registry).unwrap();
+        (registry, map)
+    }
+
+    #[test]
+    fn declarations() {
+        let (registry, map) = biomes();
+        assert_eq!(map.biomes().iter().map(|biome| biome.name()).collect::<Vec<_>>(), vec!["desert", "mountains", "plains", "tundra"]);
+
+        let desert = map.biome("desert").unwrap();
+        assert_eq!(desert.surface(), registry.block_by_name("sand").unwrap());
+        assert_eq!(desert.filler(), registry.block_by_name("stone").unwrap());
+        assert_eq!(desert.subsurface_depth(), 5);
+        assert_eq!(map.biome("plains").unwrap().subsurface_depth(), 3);
+
+        let unknown = r#"{ "swamp": { "surface": "core:mud", "subsurface": "core:dirt", "filler": "core:stone",
+            "amplitude": 2.0, "temperature": 0.0, "humidity": 0.5, "color": [0, 80, 0] } }"#;
+        let file = BiomeFile::from_str(unknown, RegistryFormat::Json).unwrap();
+        assert_eq!(BiomeMap::new(&file, &registry).unwrap_err(), GeneratorError::MissingBlock("core:mud".to_owned()));
+        assert_eq!(BiomeMap::new(&BiomeFile::default(), &registry).unwrap_err(), GeneratorError::NoBiomes);
+        assert!(BiomeFile::from_str(r#"{ "swamp": { "surface": "core:mud" } }"#, RegistryFormat::Json).is_err());
+    }
+
+    #[test]
+    fn sampling() {
+        let (_, map) = biomes();
+        let sampler = map.sampler(3);
+
+        let mut seen = Vec::new();
+        for x in (-4096..4096).step_by(64) {
+            for z in (-4096..4096).step_by(64) {
+                let weights = sampler.weights(x, z);
+                assert!((weights.iter().map(|(_, weight)| weight).sum::<f64>() - 1.0).abs() < 1e-9);
+                assert!(weights.iter().any(|(biome, _)| biome.name() == sampler.column_biome(x, z).name()));
+
+                let name = sampler.biome(x, z).name();
+                if !seen.contains(&name) {
+                    seen.push(name);
+                }
+            }
+        }
+        assert_eq!(seen.len(), map.biomes().len(), "only {:?}", seen);
+
+        // Blending keeps the terrain free of cliffs at biome borders, which it isn't without.
+        let (registry, _) = biomes();
+        let steepest = |map: BiomeMap| {
+            let generator = HeightmapGenerator::new(&registry).unwrap().biomes(map);
+            (-4096..4096).map(|x| (generator.height(3, x, 100) - generator.height(3, x + 1, 100)).abs()).max().unwrap()
+        };
+        assert!(steepest(map.clone()) <= 3);
+        assert!(steepest(map.clone().blend(0.0)) > 3);
+
+        // Generated columns are made of the blocks of their biome.
+        let generator = HeightmapGenerator::new(&registry).unwrap().biomes(map.clone());
+        let chunk = generator.generate(ChunkPosition::new(0, 0, 0), 3);
+        for (x, z) in &[(0, 0), (17, 40), (63, 63)] {
+            let biome = sampler.column_biome(*x as i64, *z as i64);
+            let height = generator.height(3, *x as i64, *z as i64) as usize;
+            assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(*x, height, *z)), biome.surface());
+            assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(*x, height - 1, *z)), biome.subsurface());
+            assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(*x, height + 1, *z)), registry.block_by_name("air").unwrap());
+        }
+    }
+
+    #[test]
+    fn image() {
+        let (_, map) = biomes();
+        let image = map.image(3, -512, -512, 256, 128);
+        let sampler = map.sampler(3);
+        for (column, row) in &[(0, 0), (255, 127), (100, 50)] {
+            let (r, g, b) = sampler.biome(-512 + *column as i64, -512 + *row as i64).color();
+            assert_eq!(image.pixel(*column, *row), [r, g, b, 255]);
+        }
+
+        let path = std::env::temp_dir().join(format!("voxel-biomes-{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        assert_eq!(RgbaImage::open(&path).unwrap(), image);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use crate::block::{Block, BlockRegistry};
+use crate::chunk::{ChunkMut, ChunkPosition, LocalBlockPosition, CHUNK_HEIGHT, CHUNK_LENGTH, CHUNK_WIDTH};
+use crate::gen::noise::{mix, FractalNoise};
+use crate::gen::{GeneratorError, GeneratorPass};
+
+pub const DEFAULT_CAVE_FREQUENCY: f64 = 1.0 / 48.0;
+pub const DEFAULT_CAVE_OCTAVES: usize = 3;
+pub const DEFAULT_CAVE_THRESHOLD: f64 = 0.25;
+pub const DEFAULT_VERTICAL_SCALE: f64 = 2.0;
+
+// Keeps the cave noise unrelated to the terrain noise of the same seed.
+const CAVE_SEED: u64 = 0x6361_7665;
+
+/// Carves caves and overhangs out of generated terrain by replacing every block where a 3D density
+/// field is above a threshold with air.
+///
+/// The density only depends on the world position, so caves continue across chunk borders.
+#[derive(Debug, Clone)]
+pub struct CaveCarver {
+    air: Block,
+    frequency: f64,
+    octaves: usize,
+    threshold: f64,
+    vertical_scale: f64,
+}
+
+impl CaveCarver {
+    /// Carver filling caves with `core:air` from `registry`.
+    pub fn new(registry: &BlockRegistry) -> Result<CaveCarver, GeneratorError> {
+        let air = registry.block_by_name("core:air").ok_or_else(|| GeneratorError::MissingBlock("core:air".to_owned()))?;
+
+        Ok(CaveCarver {
+            air,
+            frequency: DEFAULT_CAVE_FREQUENCY,
+            octaves: DEFAULT_CAVE_OCTAVES,
+            threshold: DEFAULT_CAVE_THRESHOLD,
+            vertical_scale: DEFAULT_VERTICAL_SCALE,
+        })
+    }
+
+    /// Frequency of the largest caves, in caves per block.
+    pub fn frequency(mut self, frequency: f64) -> CaveCarver {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn octaves(mut self, octaves: usize) -> CaveCarver {
+        self.octaves = octaves;
+        self
+    }
+
+    /// Density above which blocks are carved, between -1 (everything) and 1 (nothing).
+    pub fn threshold(mut self, threshold: f64) -> CaveCarver {
+        self.threshold = threshold;
+        self
+    }
+
+    /// How much faster the density changes vertically than horizontally, above 1 the caves are
+    /// wider than they are tall.
+    pub fn vertical_scale(mut self, vertical_scale: f64) -> CaveCarver {
+        self.vertical_scale = vertical_scale;
+        self
+    }
+
+    fn noise(&self, seed: u64) -> FractalNoise {
+        FractalNoise::new(mix(seed ^ CAVE_SEED), self.octaves, self.frequency)
+    }
+
+    /// Density at world position `x`, `y`, `z`, between -1 and 1.
+    pub fn density(&self, seed: u64, x: i64, y: i64, z: i64) -> f64 {
+        self.sample(&self.noise(seed), x, y, z)
+    }
+
+    /// Whether the block at world position `x`, `y`, `z` is carved out.
+    pub fn carved(&self, seed: u64, x: i64, y: i64, z: i64) -> bool {
+        self.density(seed, x, y, z) > self.threshold
+    }
+
+    fn sample(&self, noise: &FractalNoise, x: i64, y: i64, z: i64) -> f64 {
+        noise.get3(x as f64, y as f64 * self.vertical_scale, z as f64)
+    }
+}
+
+impl GeneratorPass for CaveCarver {
+    fn apply(&self, position: ChunkPosition, seed: u64, chunk: &mut dyn ChunkMut) {
+        let noise = self.noise(seed);
+        let origin = position.origin();
+
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let density = self.sample(&noise, origin.x() + x as i64, origin.y() + y as i64, origin.z() + z as i64);
+                    if density > self.threshold {
+                        chunk.set_block(&LocalBlockPosition::unchecked_new(x, y, z), self.air);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::{Block, BlockRegistry};
+    use crate::chunk::{BoxedChunk, Chunk, ChunkPosition, LocalBlockPosition, CHUNK_HEIGHT, CHUNK_LENGTH, CHUNK_WIDTH};
+    use crate::gen::{CaveCarver, ChunkGenerator, HeightmapGenerator};
+    use crate::storage::{encode_chunk, Compression};
+
+    // Blocks of the plane of `chunk` where the coordinate along `axis` is `at`.
+    fn plane(chunk: &BoxedChunk, axis: usize, at: usize) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        for a in 0..CHUNK_WIDTH {
+            for b in 0..CHUNK_LENGTH {
+                let position = match axis {
+                    0 => LocalBlockPosition::unchecked_new(at, a, b),
+                    1 => LocalBlockPosition::unchecked_new(a, at, b),
+                    _ => LocalBlockPosition::unchecked_new(a, b, at),
+                };
+                blocks.push(chunk.block(&position));
+            }
+        }
+        blocks
+    }
+
+    fn differences(a: &[Block], b: &[Block]) -> usize {
+        a.iter().zip(b).filter(|(a, b)| a != b).count()
+    }
+
+    #[test]
+    fn seamless() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let carver = CaveCarver::new(&registry).unwrap();
+        let generator = HeightmapGenerator::new(&registry).unwrap().with_pass(carver.clone());
+        let air = registry.block_by_name("air").unwrap();
+        let seed = 99;
+
+        let origin = ChunkPosition::new(0, 0, 0);
+        let encoded = |chunk: &BoxedChunk| encode_chunk(chunk, Compression::None).unwrap();
+        let chunk = encoded(&generator.generate(origin, seed));
+        assert_eq!(chunk, encoded(&generator.generate(origin, seed)));
+        assert_ne!(chunk, encoded(&generator.base().generate(origin, seed)));
+
+        // Some caves, but still mostly solid underground.
+        let below = generator.generate(ChunkPosition::new(0, -1, 0), seed);
+        let carved = (0..CHUNK_HEIGHT).map(|y| plane(&below, 1, y).into_iter().filter(|block| *block == air).count()).sum::<usize>();
+        assert!(carved > 0 && carved < CHUNK_WIDTH * CHUNK_HEIGHT * CHUNK_LENGTH / 4, "{} carved", carved);
+
+        let pairs = [
+            (ChunkPosition::new(0, 0, 0), ChunkPosition::new(1, 0, 0), 0, CHUNK_WIDTH),
+            (ChunkPosition::new(0, -1, 0), ChunkPosition::new(0, 0, 0), 1, CHUNK_HEIGHT),
+            (ChunkPosition::new(0, 0, 0), ChunkPosition::new(0, 0, 1), 2, CHUNK_LENGTH),
+        ];
+        for (lower, upper, axis, size) in &pairs {
+            let (lower, upper) = (generator.generate(*lower, seed), generator.generate(*upper, seed));
+
+            // Across the border the blocks change no more than between two planes of one chunk.
+            let across = differences(&plane(&lower, *axis, size - 1), &plane(&upper, *axis, 0));
+            let inside = differences(&plane(&lower, *axis, size - 2), &plane(&lower, *axis, size - 1));
+            assert!(across <= inside * 2 + 64, "axis {}: {} differences across the border, {} inside", axis, across, inside);
+        }
+
+        // And every block at the border is carved exactly where the density says.
+        let east = generator.generate(ChunkPosition::new(1, 0, 0), seed);
+        let plain_east = generator.base().generate(ChunkPosition::new(1, 0, 0), seed);
+        for y in 0..CHUNK_HEIGHT {
+            for z in 0..CHUNK_LENGTH {
+                let position = LocalBlockPosition::unchecked_new(0, y, z);
+                let expected = if carver.carved(seed, CHUNK_WIDTH as i64, y as i64, z as i64) { air } else { plain_east.block(&position) };
+                assert_eq!(east.block(&position), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Procedural world generation.
+
+pub mod noise;
+pub mod terrain;
+pub mod caves;
+pub mod biome;
+
+pub use self::noise::{FractalNoise, Noise};
+pub use self::terrain::HeightmapGenerator;
+pub use self::caves::CaveCarver;
+pub use self::biome::{Biome, BiomeDeclaration, BiomeFile, BiomeMap, BiomeSampler};
+
+use std::error::Error;
+use std::fmt;
+
+use crate::chunk::{BoxedChunk, ChunkMut, ChunkPosition};
+
+/// Something that generates the blocks of a chunk from its position and the world seed.
+///
+/// The result must only depend on `position` and `seed` so chunks can be generated in any order
+/// and regenerated later, identical to the last time.
+pub trait ChunkGenerator {
+    /// Set the blocks of `chunk`, which is at `position`.
+    fn fill(&self, position: ChunkPosition, seed: u64, chunk: &mut dyn ChunkMut);
+
+    fn generate(&self, position: ChunkPosition, seed: u64) -> BoxedChunk {
+        let mut chunk = BoxedChunk::empty();
+        self.fill(position, seed, &mut chunk);
+        chunk
+    }
+
+    /// This generator followed by `pass`.
+    fn with_pass<P: GeneratorPass>(self, pass: P) -> WithPass<Self, P>
+    where
+        Self: Sized,
+    {
+        WithPass { base: self, pass }
+    }
+}
+
+/// A stage that changes the blocks of a chunk after the base terrain has been generated, such as
+/// carving caves.
+///
+/// Like a `ChunkGenerator` it must only depend on `position` and `seed`, and should decide each
+/// block from its world position so chunks still line up at their borders.
+pub trait GeneratorPass {
+    fn apply(&self, position: ChunkPosition, seed: u64, chunk: &mut dyn ChunkMut);
+}
+
+/// A generator followed by a pass, see `ChunkGenerator::with_pass`.
+#[derive(Debug, Clone)]
+pub struct WithPass<G, P> {
+    base: G,
+    pass: P,
+}
+
+impl<G, P> WithPass<G, P> {
+    pub fn base(&self) -> &G {
+        &self.base
+    }
+
+    pub fn pass(&self) -> &P {
+        &self.pass
+    }
+}
+
+impl<G: ChunkGenerator, P: GeneratorPass> ChunkGenerator for WithPass<G, P> {
+    fn fill(&self, position: ChunkPosition, seed: u64, chunk: &mut dyn ChunkMut) {
+        self.base.fill(position, seed, chunk);
+        self.pass.apply(position, seed, chunk);
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GeneratorError {
+    /// A block the generator places isn't declared in the registry.
+    MissingBlock(String),
+    /// A biome map was created without any biomes.
+    NoBiomes,
+}
+
+impl fmt::Display for GeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeneratorError::MissingBlock(name) => write!(f, "block {} is not declared", name),
+            GeneratorError::NoBiomes => write!(f, "no biomes are declared"),
+        }
+    }
+}
+
+impl Error for GeneratorError {}
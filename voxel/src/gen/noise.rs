@@ -0,0 +1,203 @@
+//! Seeded gradient noise.
+//!
+//! Gradients are picked by hashing the lattice point with the seed instead of shuffling a
+//! permutation table, so any seed can be used without setup and the same inputs give the same
+//! output on every platform.
+
+/// Mix the bits of `value` (the finalizer of splitmix64).
+pub fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+/// Hash of a lattice point for `seed`.
+pub fn hash(seed: u64, x: i64, y: i64, z: i64) -> u64 {
+    let mut hash = mix(seed ^ 0x9e37_79b9_7f4a_7c15);
+    for coordinate in &[x, y, z] {
+        hash = mix(hash ^ (*coordinate as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    }
+    hash
+}
+
+const DIAGONAL: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+const GRADIENTS_2D: [(f64, f64); 8] = [
+    (1.0, 0.0),
+    (-1.0, 0.0),
+    (0.0, 1.0),
+    (0.0, -1.0),
+    (DIAGONAL, DIAGONAL),
+    (-DIAGONAL, DIAGONAL),
+    (DIAGONAL, -DIAGONAL),
+    (-DIAGONAL, -DIAGONAL),
+];
+
+// Edges of a cube, with 4 repeated so a gradient can be picked with a mask.
+const GRADIENTS_3D: [(f64, f64, f64); 16] = [
+    (1.0, 1.0, 0.0),
+    (-1.0, 1.0, 0.0),
+    (1.0, -1.0, 0.0),
+    (-1.0, -1.0, 0.0),
+    (1.0, 0.0, 1.0),
+    (-1.0, 0.0, 1.0),
+    (1.0, 0.0, -1.0),
+    (-1.0, 0.0, -1.0),
+    (0.0, 1.0, 1.0),
+    (0.0, -1.0, 1.0),
+    (0.0, 1.0, -1.0),
+    (0.0, -1.0, -1.0),
+    (1.0, 1.0, 0.0),
+    (-1.0, 1.0, 0.0),
+    (0.0, -1.0, 1.0),
+    (0.0, -1.0, -1.0),
+];
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Perlin style gradient noise, 0 at every integer point and between -1 and 1 elsewhere.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Noise {
+    seed: u64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Noise {
+        Noise { seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get2(&self, x: f64, z: f64) -> f64 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let (ix, iz) = (x0 as i64, z0 as i64);
+
+        let gradient = |dx: i64, dz: i64| {
+            // y is fixed so 2D and 3D noise of the same seed aren't related.
+            let (gx, gz) = GRADIENTS_2D[(hash(self.seed, ix + dx, i64::MIN, iz + dz) & 7) as usize];
+            gx * (fx - dx as f64) + gz * (fz - dz as f64)
+        };
+
+        let (u, v) = (fade(fx), fade(fz));
+        let value = lerp(v, lerp(u, gradient(0, 0), gradient(1, 0)), lerp(u, gradient(0, 1), gradient(1, 1)));
+        (value * std::f64::consts::SQRT_2).clamp(-1.0, 1.0)
+    }
+
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (ix, iy, iz) = (x0 as i64, y0 as i64, z0 as i64);
+
+        let gradient = |dx: i64, dy: i64, dz: i64| {
+            let (gx, gy, gz) = GRADIENTS_3D[(hash(self.seed, ix + dx, iy + dy, iz + dz) & 15) as usize];
+            gx * (fx - dx as f64) + gy * (fy - dy as f64) + gz * (fz - dz as f64)
+        };
+
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        let bottom = lerp(v, lerp(u, gradient(0, 0, 0), gradient(1, 0, 0)), lerp(u, gradient(0, 1, 0), gradient(1, 1, 0)));
+        let top = lerp(v, lerp(u, gradient(0, 0, 1), gradient(1, 0, 1)), lerp(u, gradient(0, 1, 1), gradient(1, 1, 1)));
+        lerp(w, bottom, top).clamp(-1.0, 1.0)
+    }
+}
+
+/// Several octaves of `Noise` added together, each at a higher frequency and lower amplitude than
+/// the one before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FractalNoise {
+    octaves: Vec<Noise>,
+    frequency: f64,
+    lacunarity: f64,
+    persistence: f64,
+}
+
+impl FractalNoise {
+    /// Noise with `octaves` octaves, the first of which has features about `1 / frequency` apart.
+    pub fn new(seed: u64, octaves: usize, frequency: f64) -> FractalNoise {
+        assert!(octaves > 0, "fractal noise needs at least one octave");
+        FractalNoise {
+            octaves: (0..octaves as u64).map(|octave| Noise::new(mix(seed.wrapping_add(octave)))).collect(),
+            frequency,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+
+    /// How much the frequency grows from one octave to the next, 2 by default.
+    pub fn lacunarity(mut self, lacunarity: f64) -> FractalNoise {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    /// How much the amplitude shrinks from one octave to the next, 0.5 by default.
+    pub fn persistence(mut self, persistence: f64) -> FractalNoise {
+        self.persistence = persistence;
+        self
+    }
+
+    /// Between -1 and 1.
+    pub fn get2(&self, x: f64, z: f64) -> f64 {
+        self.sum(|noise, frequency| noise.get2(x * frequency, z * frequency))
+    }
+
+    /// Between -1 and 1.
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.sum(|noise, frequency| noise.get3(x * frequency, y * frequency, z * frequency))
+    }
+
+    fn sum<F: Fn(&Noise, f64) -> f64>(&self, sample: F) -> f64 {
+        let mut total = 0.0;
+        let mut max = 0.0;
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        for noise in &self.octaves {
+            total += sample(noise, frequency) * amplitude;
+            max += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+        total / max
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gen::noise::{Noise, FractalNoise};
+
+    #[test]
+    fn noise() {
+        let noise = Noise::new(7);
+        assert_eq!(noise.get2(3.0, -5.0), 0.0);
+        assert_eq!(noise.get3(1.0, 2.0, 3.0), 0.0);
+
+        let mut differs = false;
+        for step in 0..1000 {
+            let (x, y, z) = (step as f64 * 0.173 - 80.0, step as f64 * 0.031, step as f64 * -0.257);
+            let value = noise.get3(x, y, z);
+            assert!((-1.0..=1.0).contains(&value) && (-1.0..=1.0).contains(&noise.get2(x, z)));
+            assert_eq!(value, Noise::new(7).get3(x, y, z));
+            differs |= value != Noise::new(8).get3(x, y, z);
+
+            // Continuous, so nearby points are close.
+            assert!((noise.get2(x, z) - noise.get2(x + 0.001, z)).abs() < 0.01);
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn fractal() {
+        let fractal = FractalNoise::new(42, 4, 1.0 / 64.0);
+        let values: Vec<f64> = (0..256).map(|x| fractal.get2(x as f64, 17.0)).collect();
+        assert!(values.iter().all(|value| (-1.0..=1.0).contains(value)));
+        assert!(values.iter().any(|value| *value > 0.05) && values.iter().any(|value| *value < -0.05));
+        assert_eq!(values, (0..256).map(|x| FractalNoise::new(42, 4, 1.0 / 64.0).get2(x as f64, 17.0)).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::block::{Block, BlockRegistry};
+use crate::chunk::{ChunkMut, ChunkPosition, LocalBlockPosition, CHUNK_HEIGHT, CHUNK_LENGTH, CHUNK_WIDTH};
+use crate::gen::biome::{BiomeMap, BiomeSampler};
+use crate::gen::noise::FractalNoise;
+use crate::gen::{ChunkGenerator, GeneratorError};
+
+pub const DEFAULT_BASE_HEIGHT: i64 = 32;
+pub const DEFAULT_AMPLITUDE: f64 = 24.0;
+pub const DEFAULT_FREQUENCY: f64 = 1.0 / 128.0;
+pub const DEFAULT_OCTAVES: usize = 5;
+pub const DEFAULT_DIRT_DEPTH: i64 = 4;
+
+/// Rolling hills from fractal noise: air above the surface, a few layers of dirt and stone below.
+///
+/// With biomes the amplitude and the blocks of each column come from its biome instead.
+#[derive(Debug, Clone)]
+pub struct HeightmapGenerator {
+    air: Block,
+    dirt: Block,
+    stone: Block,
+    base_height: i64,
+    amplitude: f64,
+    frequency: f64,
+    octaves: usize,
+    dirt_depth: i64,
+    biomes: Option<BiomeMap>,
+}
+
+// Surface height and blocks of a column.
+struct Column {
+    height: i64,
+    surface: Block,
+    subsurface: Block,
+    filler: Block,
+    // Layers of surface and subsurface blocks together.
+    depth: i64,
+}
+
+impl HeightmapGenerator {
+    /// Generator placing `core:air`, `core:dirt` and `core:stone` from `registry`.
+    pub fn new(registry: &BlockRegistry) -> Result<HeightmapGenerator, GeneratorError> {
+        let block = |name: &str| registry.block_by_name(name).ok_or_else(|| GeneratorError::MissingBlock(name.to_owned()));
+
+        Ok(HeightmapGenerator {
+            air: block("core:air")?,
+            dirt: block("core:dirt")?,
+            stone: block("core:stone")?,
+            base_height: DEFAULT_BASE_HEIGHT,
+            amplitude: DEFAULT_AMPLITUDE,
+            frequency: DEFAULT_FREQUENCY,
+            octaves: DEFAULT_OCTAVES,
+            dirt_depth: DEFAULT_DIRT_DEPTH,
+            biomes: None,
+        })
+    }
+
+    /// Average height of the surface.
+    pub fn base_height(mut self, base_height: i64) -> HeightmapGenerator {
+        self.base_height = base_height;
+        self
+    }
+
+    /// Largest distance of the surface from `base_height`.
+    pub fn amplitude(mut self, amplitude: f64) -> HeightmapGenerator {
+        self.amplitude = amplitude;
+        self
+    }
+
+    /// Frequency of the largest hills, in hills per block.
+    pub fn frequency(mut self, frequency: f64) -> HeightmapGenerator {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn octaves(mut self, octaves: usize) -> HeightmapGenerator {
+        self.octaves = octaves;
+        self
+    }
+
+    /// Layers of dirt on top of the stone, the surface block included.
+    pub fn dirt_depth(mut self, dirt_depth: i64) -> HeightmapGenerator {
+        self.dirt_depth = dirt_depth;
+        self
+    }
+
+    /// Take the amplitude and blocks of each column from `biomes`, replacing `amplitude` and
+    /// `dirt_depth`.
+    pub fn biomes(mut self, biomes: BiomeMap) -> HeightmapGenerator {
+        self.biomes = Some(biomes);
+        self
+    }
+
+    pub fn biome_map(&self) -> Option<&BiomeMap> {
+        self.biomes.as_ref()
+    }
+
+    fn noise(&self, seed: u64) -> FractalNoise {
+        FractalNoise::new(seed, self.octaves, self.frequency)
+    }
+
+    /// World y of the topmost solid block in the column at world `x`, `z`.
+    pub fn height(&self, seed: u64, x: i64, z: i64) -> i64 {
+        let sampler = self.biomes.as_ref().map(|biomes| biomes.sampler(seed));
+        self.column(&self.noise(seed), sampler.as_ref(), x, z).height
+    }
+
+    fn column(&self, noise: &FractalNoise, sampler: Option<&BiomeSampler<'_>>, x: i64, z: i64) -> Column {
+        let offset = noise.get2(x as f64, z as f64);
+        match sampler {
+            Some(sampler) => {
+                let biome = sampler.column_biome(x, z);
+                Column {
+                    height: self.base_height + (offset * sampler.amplitude(x, z)).floor() as i64,
+                    surface: biome.surface(),
+                    subsurface: biome.subsurface(),
+                    filler: biome.filler(),
+                    depth: 1 + biome.subsurface_depth(),
+                }
+            },
+            None => Column {
+                height: self.base_height + (offset * self.amplitude).floor() as i64,
+                surface: self.dirt,
+                subsurface: self.dirt,
+                filler: self.stone,
+                depth: self.dirt_depth,
+            },
+        }
+    }
+}
+
+impl ChunkGenerator for HeightmapGenerator {
+    fn fill(&self, position: ChunkPosition, seed: u64, chunk: &mut dyn ChunkMut) {
+        let noise = self.noise(seed);
+        let sampler = self.biomes.as_ref().map(|biomes| biomes.sampler(seed));
+        let origin = position.origin();
+
+        for x in 0..CHUNK_WIDTH {
+            for z in 0..CHUNK_LENGTH {
+                let column = self.column(&noise, sampler.as_ref(), origin.x() + x as i64, origin.z() + z as i64);
+
+                for y in 0..CHUNK_HEIGHT {
+                    let world_y = origin.y() + y as i64;
+                    let block = if world_y > column.height {
+                        self.air
+                    } else if world_y == column.height {
+                        column.surface
+                    } else if world_y > column.height - column.depth {
+                        column.subsurface
+                    } else {
+                        column.filler
+                    };
+                    chunk.set_block(&LocalBlockPosition::unchecked_new(x, y, z), block);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::BlockRegistry;
+    use crate::chunk::{Chunk, ChunkPosition, LocalBlockPosition, CHUNK_HEIGHT, CHUNK_WIDTH};
+    use crate::gen::{ChunkGenerator, GeneratorError, HeightmapGenerator};
+    use crate::storage::{encode_chunk, Compression};
+
+    #[test]
+    fn deterministic() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let generator = HeightmapGenerator::new(&registry).unwrap();
+
+        for position in &[ChunkPosition::new(0, 0, 0), ChunkPosition::new(-3, 0, 7), ChunkPosition::new(1, -1, -1)] {
+            let first = encode_chunk(&generator.generate(*position, 1234), Compression::None).unwrap();
+            let again = encode_chunk(&generator.generate(*position, 1234), Compression::None).unwrap();
+            assert_eq!(first, again);
+
+            // Chunks below the lowest possible surface are stone for every seed.
+            let other = encode_chunk(&generator.generate(*position, 4321), Compression::None).unwrap();
+            assert_eq!(first == other, position.y() < 0);
+        }
+
+        // Pinned so a change to the noise, which would change every existing world, is noticed.
+        let heights: Vec<i64> = [(37, -91), (500, -250), (-1000, 2000), (4096, 77)].iter().map(|(x, z)| generator.height(1234, *x, *z)).collect();
+        assert_eq!(heights, vec![38, 31, 34, 34]);
+    }
+
+    #[test]
+    fn layers() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let generator = HeightmapGenerator::new(&registry).unwrap().dirt_depth(3);
+        let (air, dirt, stone) = (
+            registry.block_by_name("air").unwrap(),
+            registry.block_by_name("dirt").unwrap(),
+            registry.block_by_name("stone").unwrap(),
+        );
+
+        let chunk = generator.generate(ChunkPosition::new(0, 0, 0), 5);
+        let east = generator.generate(ChunkPosition::new(1, 0, 0), 5);
+        for x in 0..CHUNK_WIDTH {
+            let height = generator.height(5, x as i64, 9);
+            assert!(height > 3 && height < CHUNK_HEIGHT as i64);
+            for y in 0..CHUNK_HEIGHT {
+                let expected = match height - y as i64 {
+                    depth if depth < 0 => air,
+                    depth if depth < 3 => dirt,
+                    _ => stone,
+                };
+                assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(x, y, 9)), expected);
+            }
+        }
+
+        // The surface doesn't jump at the border between two chunks.
+        for z in 0..CHUNK_WIDTH {
+            let last = generator.height(5, CHUNK_WIDTH as i64 - 1, z as i64);
+            let first = generator.height(5, CHUNK_WIDTH as i64, z as i64);
+            assert!((last - first).abs() <= 2);
+            assert_eq!(east.block(&LocalBlockPosition::unchecked_new(0, first as usize, z)), dirt);
+        }
+
+        let (empty, _) = BlockRegistry::from_str("{}").unwrap();
+        assert_eq!(HeightmapGenerator::new(&empty).unwrap_err(), GeneratorError::MissingBlock("core:air".to_owned()));
+    }
+}
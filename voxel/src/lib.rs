@@ -5,7 +5,23 @@ extern crate util;
 #[macro_use]
 extern crate serde;
 extern crate serde_json;
+extern crate ron;
+extern crate toml;
+extern crate png;
+extern crate arc_swap;
+extern crate crc32fast;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "lz4")]
+extern crate lz4;
 
 pub mod chunk;
 pub mod block;
+pub mod mesh;
+pub mod world;
+pub mod chunk_map;
+pub mod texture;
+pub mod storage;
+pub mod vox;
 
+pub mod gen;
@@ -0,0 +1,381 @@
+//! Conversion of chunks into renderable vertex and index buffers.
+//!
+//! Positions are in chunk local units where a block is 1.0 across, and the vertex layout matches
+//! the `position`, `color`, `normal` attributes the renderer's basic shader expects.
+
+use crate::block::{Block, BlockRegistry, BlockDeclaration};
+use crate::chunk::{Chunk, LocalBlockPosition, CHUNK_HEIGHT, CHUNK_WIDTH, CHUNK_LENGTH};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Face {
+    PosX,
+    PosY,
+    PosZ,
+    NegX,
+    NegY,
+    NegZ,
+}
+
+impl Face {
+    /// Same order as `LocalBlockPosition::possible_surrounding`.
+    pub const ALL: [Face; 6] = [Face::PosX, Face::PosY, Face::PosZ, Face::NegX, Face::NegY, Face::NegZ];
+
+    pub fn normal(&self) -> [f32; 3] {
+        match self {
+            Face::PosX => [1.0, 0.0, 0.0],
+            Face::PosY => [0.0, 1.0, 0.0],
+            Face::PosZ => [0.0, 0.0, 1.0],
+            Face::NegX => [-1.0, 0.0, 0.0],
+            Face::NegY => [0.0, -1.0, 0.0],
+            Face::NegZ => [0.0, 0.0, -1.0],
+        }
+    }
+
+    /// Index of this face in `Face::ALL`.
+    pub fn index(&self) -> usize {
+        match self {
+            Face::PosX => 0,
+            Face::PosY => 1,
+            Face::PosZ => 2,
+            Face::NegX => 3,
+            Face::NegY => 4,
+            Face::NegZ => 5,
+        }
+    }
+
+    pub fn offset(&self) -> (isize, isize, isize) {
+        match self {
+            Face::PosX => (1, 0, 0),
+            Face::PosY => (0, 1, 0),
+            Face::PosZ => (0, 0, 1),
+            Face::NegX => (-1, 0, 0),
+            Face::NegY => (0, -1, 0),
+            Face::NegZ => (0, 0, -1),
+        }
+    }
+
+    /// Position of the block this face looks at, `None` if it is outside of the chunk.
+    pub fn neighbor(&self, position: &LocalBlockPosition) -> Option<LocalBlockPosition> {
+        let (x, y, z) = (position.x(), position.y(), position.z());
+        match self {
+            Face::PosX => LocalBlockPosition::new(x + 1, y, z),
+            Face::PosY => LocalBlockPosition::new(x, y + 1, z),
+            Face::PosZ => LocalBlockPosition::new(x, y, z + 1),
+            Face::NegX => LocalBlockPosition::new(x.checked_sub(1)?, y, z),
+            Face::NegY => LocalBlockPosition::new(x, y.checked_sub(1)?, z),
+            Face::NegZ => LocalBlockPosition::new(x, y, z.checked_sub(1)?),
+        }
+    }
+
+    // Axis the face points along followed by the two axes spanning the face.
+    fn axes(&self) -> (usize, usize, usize) {
+        match self {
+            Face::PosX | Face::NegX => (0, 1, 2),
+            Face::PosY | Face::NegY => (1, 0, 2),
+            Face::PosZ | Face::NegZ => (2, 0, 1),
+        }
+    }
+
+    // Corners of the face on a unit cube, counter-clockwise when looking at the face from outside.
+    fn corners(&self) -> [[f32; 3]; 4] {
+        match self {
+            Face::PosX => [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 1.0]],
+            Face::PosY => [[0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 0.0]],
+            Face::PosZ => [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]],
+            Face::NegX => [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0]],
+            Face::NegY => [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]],
+            Face::NegZ => [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MeshMode {
+    /// One quad per visible block face.
+    Culled,
+    /// Coplanar visible faces of the same block are merged into larger quads.
+    Greedy,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    pub normal: [f32; 3],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChunkMesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    pub fn empty() -> ChunkMesh {
+        ChunkMesh::default()
+    }
+
+    pub fn build<C: Chunk + ?Sized>(chunk: &C, registry: &BlockRegistry, mode: MeshMode) -> ChunkMesh {
+        match mode {
+            MeshMode::Culled => ChunkMesh::culled(chunk, registry),
+            MeshMode::Greedy => ChunkMesh::greedy(chunk, registry),
+        }
+    }
+
+    /// Mesh every block face that borders a transparent block.
+    ///
+    /// Faces on the chunk border are always emitted since the neighbouring chunk is unknown, and
+    /// blocks without a declaration are treated as air.
+    pub fn culled<C: Chunk + ?Sized>(chunk: &C, registry: &BlockRegistry) -> ChunkMesh {
+        let mut mesh = ChunkMesh::empty();
+
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let position = LocalBlockPosition::unchecked_new(x, y, z);
+                    let declaration = match registry.declaration(chunk.block(&position)) {
+                        Some(declaration) if declaration.visible() => declaration,
+                        _ => continue,
+                    };
+
+                    let color = vertex_color(declaration);
+                    let origin = [x as f32, y as f32, z as f32];
+                    for face in Face::ALL.iter() {
+                        if face_visible(chunk, registry, &position, *face) {
+                            mesh.push_quad(*face, origin, [1.0, 1.0, 1.0], color);
+                        }
+                    }
+                }
+            }
+        }
+
+        mesh
+    }
+
+    /// Mesh the same faces as `culled`, merging coplanar faces of the same block into rectangles.
+    ///
+    /// Each slice of the chunk along a face's axis is reduced to a 2D mask of visible faces, which
+    /// is then covered by growing rectangles first along the rows and then across them.
+    pub fn greedy<C: Chunk + ?Sized>(chunk: &C, registry: &BlockRegistry) -> ChunkMesh {
+        let mut mesh = ChunkMesh::empty();
+        let dimensions = [CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH];
+        let mut mask: Vec<Option<Block>> = Vec::new();
+
+        for face in Face::ALL.iter() {
+            let (d, u, v) = face.axes();
+            let (width, height) = (dimensions[u], dimensions[v]);
+            mask.clear();
+            mask.resize(width * height, None);
+
+            for slice in 0..dimensions[d] {
+                let at = |a: usize, b: usize| {
+                    let mut coordinates = [0; 3];
+                    coordinates[d] = slice;
+                    coordinates[u] = a;
+                    coordinates[v] = b;
+                    coordinates
+                };
+
+                for b in 0..height {
+                    for a in 0..width {
+                        let [x, y, z] = at(a, b);
+                        let position = LocalBlockPosition::unchecked_new(x, y, z);
+                        let block = chunk.block(&position);
+                        mask[b * width + a] = match registry.declaration(block) {
+                            Some(declaration) if declaration.visible()
+                                && face_visible(chunk, registry, &position, *face) => Some(block),
+                            _ => None,
+                        };
+                    }
+                }
+
+                for b in 0..height {
+                    let mut a = 0;
+                    while a < width {
+                        let block = match mask[b * width + a] {
+                            Some(block) => block,
+                            None => {
+                                a += 1;
+                                continue;
+                            }
+                        };
+
+                        let mut quad_width = 1;
+                        while a + quad_width < width && mask[b * width + a + quad_width] == Some(block) {
+                            quad_width += 1;
+                        }
+
+                        let mut quad_height = 1;
+                        while b + quad_height < height
+                            && mask[(b + quad_height) * width + a..][..quad_width].iter().all(|masked| *masked == Some(block))
+                        {
+                            quad_height += 1;
+                        }
+
+                        for row in 0..quad_height {
+                            for masked in &mut mask[(b + row) * width + a..][..quad_width] {
+                                *masked = None;
+                            }
+                        }
+
+                        let origin = at(a, b);
+                        let mut size = [1.0; 3];
+                        size[u] = quad_width as f32;
+                        size[v] = quad_height as f32;
+
+                        let declaration = registry.declaration(block).as_ref().unwrap();
+                        mesh.push_quad(
+                            *face,
+                            [origin[0] as f32, origin[1] as f32, origin[2] as f32],
+                            size,
+                            vertex_color(declaration),
+                        );
+
+                        a += quad_width;
+                    }
+                }
+            }
+        }
+
+        mesh
+    }
+
+    pub fn quad_count(&self) -> usize {
+        self.indices.len() / 6
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    // Pushes a quad for `face` of the box at `origin` with the given `size` along each axis.
+    fn push_quad(&mut self, face: Face, origin: [f32; 3], size: [f32; 3], color: [f32; 4]) {
+        let start = self.vertices.len() as u32;
+        let normal = face.normal();
+        for corner in face.corners().iter() {
+            self.vertices.push(MeshVertex {
+                position: [
+                    origin[0] + corner[0] * size[0],
+                    origin[1] + corner[1] * size[1],
+                    origin[2] + corner[2] * size[2],
+                ],
+                color,
+                normal,
+            });
+        }
+
+        self.indices.extend_from_slice(&[start, start + 1, start + 2, start, start + 2, start + 3]);
+    }
+}
+
+fn vertex_color(declaration: &BlockDeclaration) -> [f32; 4] {
+    let (r, g, b) = declaration.color();
+    [
+        r as f32 / 255.0,
+        g as f32 / 255.0,
+        b as f32 / 255.0,
+        1.0 - declaration.transparency() as f32 / 255.0,
+    ]
+}
+
+fn face_visible<C: Chunk + ?Sized>(chunk: &C, registry: &BlockRegistry, position: &LocalBlockPosition, face: Face) -> bool {
+    match face.neighbor(position) {
+        Some(neighbor) => match registry.declaration(chunk.block(&neighbor)) {
+            Some(declaration) => !declaration.opaque(),
+            None => true,
+        },
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mesh::{ChunkMesh, MeshMode};
+    use crate::chunk::{ChunkMut, BoxedChunk, LocalBlockPosition, CHUNK_WIDTH, CHUNK_LENGTH};
+    use crate::block::{Block, BlockRegistry};
+
+    #[test]
+    fn single_block() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let mut chunk = BoxedChunk::empty();
+        chunk.set_block(&LocalBlockPosition::unchecked_new(3, 3, 3), Block::hard_create(1));
+
+        let mesh = ChunkMesh::culled(&chunk, &registry);
+        assert_eq!(mesh.quad_count(), 6);
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_eq!(mesh.indices.len(), 36);
+
+        // Dirt is declared as (165, 42, 42).
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.color, [165.0 / 255.0, 42.0 / 255.0, 42.0 / 255.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn shared_faces_culled() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let mut chunk = BoxedChunk::empty();
+        chunk.set_block(&LocalBlockPosition::unchecked_new(3, 3, 3), Block::hard_create(1));
+        chunk.set_block(&LocalBlockPosition::unchecked_new(4, 3, 3), Block::hard_create(1));
+
+        let mesh = ChunkMesh::culled(&chunk.get_ref(), &registry);
+        assert_eq!(mesh.quad_count(), 10);
+    }
+
+    #[test]
+    fn flat_chunk() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let chunk = BoxedChunk::flat(Block::hard_create(1), 5);
+
+        // Top and bottom of every block plus the four sides of the layer.
+        let width = CHUNK_WIDTH - 1;
+        let length = CHUNK_LENGTH - 1;
+        let mesh = ChunkMesh::culled(&chunk, &registry);
+        assert_eq!(mesh.quad_count(), 2 * width * length + 2 * width + 2 * length);
+    }
+
+    // Total area covered by the quads of a mesh.
+    fn mesh_area(mesh: &ChunkMesh) -> f32 {
+        mesh.vertices
+            .chunks(4)
+            .map(|quad| {
+                let diagonal = [
+                    (quad[2].position[0] - quad[0].position[0]).abs(),
+                    (quad[2].position[1] - quad[0].position[1]).abs(),
+                    (quad[2].position[2] - quad[0].position[2]).abs(),
+                ];
+                diagonal.iter().filter(|extent| **extent > 0.0).product::<f32>()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn greedy_flat_chunk() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let chunk = BoxedChunk::flat(Block::hard_create(1), 5);
+
+        let greedy = ChunkMesh::build(&chunk, &registry, MeshMode::Greedy);
+        let culled = ChunkMesh::build(&chunk, &registry, MeshMode::Culled);
+        assert_eq!(greedy.quad_count(), 6);
+        assert_eq!(mesh_area(&greedy), mesh_area(&culled));
+    }
+
+    #[test]
+    fn greedy_matches_culled_area() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let mut chunk = BoxedChunk::empty();
+        for x in 0..12 {
+            for y in 0..(x % 5 + 1) {
+                for z in 0..(x * 3 % 7 + 2) {
+                    chunk.set_block(&LocalBlockPosition::unchecked_new(x, y, z), Block::hard_create(1));
+                }
+            }
+        }
+
+        let greedy = ChunkMesh::greedy(&chunk, &registry);
+        let culled = ChunkMesh::culled(&chunk, &registry);
+        assert!(greedy.quad_count() < culled.quad_count());
+        assert_eq!(mesh_area(&greedy), mesh_area(&culled));
+    }
+}
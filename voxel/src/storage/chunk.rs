@@ -0,0 +1,439 @@
+//! Binary format for a single chunk.
+//!
+//! A chunk is written as a fixed header followed by its body, which may be compressed. All header
+//! fields are little endian:
+//!
+//! | bytes | field                                                 |
+//! |-------|-------------------------------------------------------|
+//! | 4     | magic, `VXCH`                                         |
+//! | 2     | format version                                        |
+//! | 1     | compression, 0 for none, 1 for zstd and 2 for lz4     |
+//! | 1     | reserved, always 0                                    |
+//! | 2 × 3 | chunk width, height and length                        |
+//! | 4     | length of the uncompressed body                       |
+//! | 4     | length of the body as stored                          |
+//!
+//! The body is made of varints. It starts with the number of palette entries followed by the id
+//! and state of each, then runs of `(palette index, length)` covering every block in
+//! `chunk_index` order. Ids are varints so the format doesn't depend on the block id width.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+
+use crate::block::{Block, BlockSize, BlockState, MAX_BLOCK_ID};
+use crate::chunk::{Chunk, ChunkMut, LocalBlockPosition, CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH, CHUNK_SIZE};
+use crate::storage::{StorageError, write_varint, read_varint};
+
+pub const CHUNK_MAGIC: [u8; 4] = *b"VXCH";
+pub const CHUNK_FORMAT_VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 22;
+
+// Every block in its own palette entry and run, with the widest varints for each field.
+const MAX_BODY_LEN: usize = 3 + CHUNK_SIZE * (5 + 3 + 3 + 3);
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    /// Requires the `zstd` feature.
+    Zstd,
+    /// Requires the `lz4` feature.
+    Lz4,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 3] = [Compression::None, Compression::Zstd, Compression::Lz4];
+
+    /// Whether this build can read and write data with this compression.
+    pub fn is_available(&self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Zstd => cfg!(feature = "zstd"),
+            Compression::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+            Compression::Lz4 => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Compression, StorageError> {
+        Compression::ALL
+            .iter()
+            .find(|compression| compression.tag() == tag)
+            .cloned()
+            .ok_or(StorageError::UnknownCompression(tag))
+    }
+
+    fn compress(&self, body: Vec<u8>) -> Result<Vec<u8>, StorageError> {
+        match self {
+            Compression::None => Ok(body),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::encode_all(&body[..], ZSTD_LEVEL)?),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4::block::compress(&body, None, false)?),
+            #[allow(unreachable_patterns)]
+            _ => Err(StorageError::CompressionUnavailable(*self)),
+        }
+    }
+
+    fn decompress(&self, stored: Vec<u8>, body_len: usize) -> Result<Vec<u8>, StorageError> {
+        let body = match self {
+            Compression::None => stored,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                // Limited to the expected length so a corrupt chunk can't expand without bound.
+                let mut body = Vec::with_capacity(body_len);
+                zstd::Decoder::new(&stored[..])?.take(body_len as u64 + 1).read_to_end(&mut body)?;
+                body
+            },
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4::block::decompress(&stored, Some(body_len as i32))?,
+            #[allow(unreachable_patterns)]
+            _ => return Err(StorageError::CompressionUnavailable(*self)),
+        };
+
+        if body.len() != body_len {
+            return Err(StorageError::corrupt(format!(
+                "body is {} bytes instead of {}",
+                body.len(),
+                body_len
+            )));
+        }
+
+        Ok(body)
+    }
+}
+
+/// No compression.
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::None
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "no"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+/// Encode every block of `chunk`, header included.
+pub fn encode_chunk<C: Chunk + ?Sized>(chunk: &C, compression: Compression) -> Result<Vec<u8>, StorageError> {
+    let mut palette: Vec<Block> = Vec::new();
+    let mut lookup: HashMap<Block, usize> = HashMap::new();
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    // Chunks are mostly long runs of the same block, so remember the last lookup.
+    let mut last: Option<(Block, usize)> = None;
+
+    for y in 0..CHUNK_HEIGHT {
+        for x in 0..CHUNK_WIDTH {
+            for z in 0..CHUNK_LENGTH {
+                let block = chunk.block(&LocalBlockPosition::unchecked_new(x, y, z));
+                let index = match last {
+                    Some((last_block, index)) if last_block == block => index,
+                    _ => {
+                        let index = *lookup.entry(block).or_insert_with(|| {
+                            palette.push(block);
+                            palette.len() - 1
+                        });
+                        last = Some((block, index));
+                        index
+                    },
+                };
+
+                match runs.last_mut() {
+                    Some((run_index, length)) if *run_index == index => *length += 1,
+                    _ => runs.push((index, 1)),
+                }
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    write_varint(&mut body, palette.len() as u64);
+    for block in &palette {
+        write_varint(&mut body, block.id() as u64);
+        write_varint(&mut body, block.state() as u64);
+    }
+    for (index, length) in runs {
+        write_varint(&mut body, index as u64);
+        write_varint(&mut body, length as u64);
+    }
+
+    let body_len = body.len();
+    let stored = compression.compress(body)?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + stored.len());
+    out.extend_from_slice(&CHUNK_MAGIC);
+    out.extend_from_slice(&CHUNK_FORMAT_VERSION.to_le_bytes());
+    out.push(compression.tag());
+    out.push(0);
+    for dimension in &[CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH] {
+        out.extend_from_slice(&(*dimension as u16).to_le_bytes());
+    }
+    out.extend_from_slice(&(body_len as u32).to_le_bytes());
+    out.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+    out.extend_from_slice(&stored);
+    Ok(out)
+}
+
+/// Decode a chunk written by `encode_chunk`, which has to be all of `bytes`.
+pub fn decode_chunk<C: ChunkMut + Default>(mut bytes: &[u8]) -> Result<C, StorageError> {
+    let chunk = read_chunk(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(StorageError::corrupt(format!("{} bytes after the chunk", bytes.len())));
+    }
+    Ok(chunk)
+}
+
+/// Read one chunk written by `encode_chunk` from `reader`, leaving anything after it unread.
+pub fn read_chunk<R: Read, C: ChunkMut + Default>(reader: &mut R) -> Result<C, StorageError> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+
+    let u16_at = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+
+    if header[0..4] != CHUNK_MAGIC {
+        return Err(StorageError::InvalidMagic);
+    }
+
+    let version = u16_at(4);
+    if version > CHUNK_FORMAT_VERSION {
+        return Err(StorageError::UnsupportedVersion(version));
+    }
+
+    let compression = Compression::from_tag(header[6])?;
+    let (width, height, length) = (u16_at(8) as usize, u16_at(10) as usize, u16_at(12) as usize);
+    if (width, height, length) != (CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH) {
+        return Err(StorageError::DimensionMismatch { width, height, length });
+    }
+
+    let body_len = u32_at(14) as usize;
+    if body_len > MAX_BODY_LEN {
+        return Err(StorageError::corrupt(format!("body length {} is too large", body_len)));
+    }
+
+    let stored_len = u32_at(18) as u64;
+    let mut stored = Vec::new();
+    reader.take(stored_len).read_to_end(&mut stored)?;
+    if stored.len() as u64 != stored_len {
+        return Err(StorageError::corrupt("unexpected end of data"));
+    }
+
+    let body = compression.decompress(stored, body_len)?;
+    decode_body(&body)
+}
+
+fn decode_body<C: ChunkMut + Default>(mut body: &[u8]) -> Result<C, StorageError> {
+    let body = &mut body;
+
+    let palette_len = read_varint(body)? as usize;
+    if palette_len == 0 || palette_len > CHUNK_SIZE {
+        return Err(StorageError::corrupt(format!("invalid palette length {}", palette_len)));
+    }
+
+    let mut palette = Vec::with_capacity(palette_len);
+    for _ in 0..palette_len {
+        let id = read_varint(body)?;
+        if id > MAX_BLOCK_ID as u64 {
+            return Err(StorageError::BlockOutOfRange { id, max: MAX_BLOCK_ID });
+        }
+
+        let state = read_varint(body)?;
+        if state > BlockState::MAX as u64 {
+            return Err(StorageError::corrupt(format!("invalid block state {}", state)));
+        }
+
+        palette.push(Block::with_state(id as BlockSize, state as BlockState));
+    }
+
+    let mut chunk = C::default();
+    let mut index = 0;
+    while index < CHUNK_SIZE {
+        let entry = read_varint(body)? as usize;
+        let block = *palette
+            .get(entry)
+            .ok_or_else(|| StorageError::corrupt(format!("palette index {} is out of range", entry)))?;
+
+        let run = read_varint(body)? as usize;
+        if run == 0 || run > CHUNK_SIZE - index {
+            return Err(StorageError::corrupt(format!("invalid run length {}", run)));
+        }
+
+        for index in index..(index + run) {
+            chunk.set_block(&position_of(index), block);
+        }
+        index += run;
+    }
+
+    if !body.is_empty() {
+        return Err(StorageError::corrupt("runs cover more than one chunk"));
+    }
+
+    Ok(chunk)
+}
+
+// Inverse of `chunk_index`.
+fn position_of(index: usize) -> LocalBlockPosition {
+    let y = index / (CHUNK_WIDTH * CHUNK_LENGTH);
+    let x = (index / CHUNK_LENGTH) % CHUNK_WIDTH;
+    let z = index % CHUNK_LENGTH;
+    LocalBlockPosition::unchecked_new(x, y, z)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::{Block, BlockSize, BlockState};
+    use crate::chunk::{Chunk, ChunkMut, BoxedChunk, PalettedChunk, LocalBlockPosition, CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH};
+    use crate::storage::{encode_chunk, decode_chunk, read_chunk, Compression, StorageError};
+
+    fn assert_same(expected: &dyn Chunk, actual: &dyn Chunk) {
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    let position = LocalBlockPosition::unchecked_new(x, y, z);
+                    assert_eq!(expected.block(&position), actual.block(&position), "{:?}", position);
+                }
+            }
+        }
+    }
+
+    fn mixed() -> BoxedChunk {
+        let mut chunk = BoxedChunk::flat(Block::hard_create(1), 10);
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_LENGTH {
+                    if (x * 7 + y * 3 + z) % 11 == 0 {
+                        let block = Block::with_state(((x + z) % 100) as BlockSize, (y % 4) as BlockState);
+                        chunk.set_block(&LocalBlockPosition::unchecked_new(x, y, z), block);
+                    }
+                }
+            }
+        }
+        chunk
+    }
+
+    // Uncompressed chunk data with the given body.
+    fn with_body(body: &[u8]) -> Vec<u8> {
+        let mut bytes = encode_chunk(&BoxedChunk::empty(), Compression::None).unwrap()[..22].to_vec();
+        bytes[14..18].copy_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes[18..22].copy_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn available() -> impl Iterator<Item = Compression> {
+        Compression::ALL.iter().cloned().filter(|compression| compression.is_available())
+    }
+
+    #[test]
+    fn empty_and_flat() {
+        for compression in available() {
+            let empty = BoxedChunk::empty();
+            let bytes = encode_chunk(&empty, compression).unwrap();
+            assert_same(&empty, &decode_chunk::<BoxedChunk>(&bytes).unwrap());
+
+            for y in &[0, 31, CHUNK_HEIGHT - 1] {
+                let flat = BoxedChunk::flat(Block::with_state(3, 2), *y);
+                let bytes = encode_chunk(&flat, compression).unwrap();
+                assert_same(&flat, &decode_chunk::<BoxedChunk>(&bytes).unwrap());
+                assert_same(&flat, &decode_chunk::<PalettedChunk>(&bytes).unwrap());
+            }
+        }
+
+        // Header, palette and a single run.
+        assert_eq!(encode_chunk(&BoxedChunk::empty(), Compression::None).unwrap().len(), 22 + 7);
+        let flat = encode_chunk(&BoxedChunk::flat(Block::hard_create(1), 5), Compression::None).unwrap();
+        assert!(flat.len() < 22 + 400, "{} bytes", flat.len());
+    }
+
+    #[test]
+    fn mixed_blocks() {
+        let chunk = mixed();
+        for compression in available() {
+            let bytes = encode_chunk(&chunk, compression).unwrap();
+            assert_same(&chunk, &decode_chunk::<BoxedChunk>(&bytes).unwrap());
+            assert_same(&chunk, &decode_chunk::<PalettedChunk>(&bytes).unwrap());
+
+            // Chunks can be read one after another from a stream.
+            let mut stream = bytes.clone();
+            stream.extend_from_slice(&encode_chunk(&BoxedChunk::empty(), compression).unwrap());
+            let mut reader = &stream[..];
+            assert_same(&chunk, &read_chunk::<_, BoxedChunk>(&mut reader).unwrap());
+            assert_same(&BoxedChunk::empty(), &read_chunk::<_, BoxedChunk>(&mut reader).unwrap());
+            assert!(reader.is_empty());
+        }
+
+        let paletted = PalettedChunk::from_chunk(&chunk);
+        let bytes = encode_chunk(&paletted, Compression::None).unwrap();
+        assert_eq!(bytes, encode_chunk(&chunk, Compression::None).unwrap());
+    }
+
+    #[test]
+    fn compression() {
+        let chunk = mixed();
+        let uncompressed = encode_chunk(&chunk, Compression::None).unwrap();
+        for compression in &[Compression::Zstd, Compression::Lz4] {
+            match encode_chunk(&chunk, *compression) {
+                Ok(bytes) => assert!(bytes.len() < uncompressed.len()),
+                Err(StorageError::CompressionUnavailable(unavailable)) => {
+                    assert_eq!(unavailable, *compression);
+                    assert!(!compression.is_available());
+                },
+                Err(error) => panic!("unexpected error {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let bytes = encode_chunk(&BoxedChunk::flat(Block::hard_create(2), 1), Compression::None).unwrap();
+        let decode = |bytes: &[u8]| decode_chunk::<BoxedChunk>(bytes).unwrap_err();
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(matches!(decode(&magic), StorageError::InvalidMagic));
+
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert!(matches!(decode(&version), StorageError::UnsupportedVersion(9)));
+
+        let mut compression = bytes.clone();
+        compression[6] = 7;
+        assert!(matches!(decode(&compression), StorageError::UnknownCompression(7)));
+
+        let mut dimensions = bytes.clone();
+        dimensions[8] = 32;
+        assert!(matches!(decode(&dimensions), StorageError::DimensionMismatch { width: 32, .. }));
+
+        assert!(matches!(decode(&bytes[..10]), StorageError::IO(_)));
+        assert!(matches!(decode(&bytes[..bytes.len() - 1]), StorageError::Corrupt(_)));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(decode(&trailing), StorageError::Corrupt(_)));
+
+        // Palette index out of range, run past the end of the chunk and a block id too large for any width.
+        assert!(matches!(decode(&with_body(&[1, 0, 0, 1, 0x80, 0x80, 0x10])), StorageError::Corrupt(_)));
+        assert!(matches!(decode(&with_body(&[1, 0, 0, 0, 0x81, 0x80, 0x10])), StorageError::Corrupt(_)));
+        let large = decode(&with_body(&[1, 0x80, 0x80, 0x80, 0x80, 0x10, 0, 0, 0x80, 0x80, 0x10]));
+        assert!(matches!(large, StorageError::BlockOutOfRange { id: 0x1_0000_0000, .. }));
+
+        let empty = with_body(&[1, 0, 0, 0, 0x80, 0x80, 0x10]);
+        assert_same(&BoxedChunk::empty(), &decode_chunk::<BoxedChunk>(&empty).unwrap());
+    }
+}
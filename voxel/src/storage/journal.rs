@@ -0,0 +1,276 @@
+//! Write-ahead journal of chunk writes, so a world survives a crash in the middle of saving.
+//!
+//! Writes are appended to the journal and synced to disk before any region file is touched, and
+//! the journal is cleared once the region files have been synced as well. If the process dies in
+//! between, the next `WorldStorage::open` replays the journal. Replaying the same writes twice is
+//! harmless since each record holds the complete data of a chunk.
+//!
+//! After an 8 byte header (magic `VXJN`, format version and 2 reserved bytes) the journal is a
+//! sequence of records, each a little endian `u32` payload length and crc32 of the payload followed
+//! by the payload itself. A payload is a kind byte, then for writes and removals the chunk position
+//! as three `i32`s, then for writes the chunk data. Records only count once a commit record follows
+//! them, so a transaction cut short by a crash is ignored as a whole.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::chunk::ChunkPosition;
+use crate::storage::StorageError;
+
+pub const JOURNAL_MAGIC: [u8; 4] = *b"VXJN";
+pub const JOURNAL_FORMAT_VERSION: u16 = 1;
+
+const HEADER_LEN: u64 = 8;
+
+const KIND_WRITE: u8 = 1;
+const KIND_REMOVE: u8 = 2;
+const KIND_COMMIT: u8 = 3;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum JournalEntry {
+    /// New data for a chunk, as written by `encode_chunk`.
+    Write {
+        position: ChunkPosition,
+        data: Vec<u8>,
+    },
+    Remove(ChunkPosition),
+}
+
+impl JournalEntry {
+    pub fn position(&self) -> ChunkPosition {
+        match self {
+            JournalEntry::Write { position, .. } => *position,
+            JournalEntry::Remove(position) => *position,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Open the journal at `path`, creating an empty one if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Journal, StorageError> {
+        let path = path.as_ref().to_owned();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+
+        if file.metadata()?.len() < HEADER_LEN {
+            // Also covers a crash while the header itself was being written.
+            let mut header = [0u8; HEADER_LEN as usize];
+            header[0..4].copy_from_slice(&JOURNAL_MAGIC);
+            header[4..6].copy_from_slice(&JOURNAL_FORMAT_VERSION.to_le_bytes());
+            file.set_len(0)?;
+            file.write_all(&header)?;
+            file.sync_all()?;
+        } else {
+            let mut header = [0u8; HEADER_LEN as usize];
+            file.read_exact(&mut header)?;
+            if header[0..4] != JOURNAL_MAGIC {
+                return Err(StorageError::InvalidMagic);
+            }
+
+            let version = u16::from_le_bytes([header[4], header[5]]);
+            if version > JOURNAL_FORMAT_VERSION {
+                return Err(StorageError::UnsupportedVersion(version));
+            }
+        }
+
+        let mut journal = Journal { path, file };
+
+        // Drop anything after the last commit, or later transactions would be appended after it and
+        // never be read.
+        let (_, end) = journal.scan()?;
+        if end < journal.file.metadata()?.len() {
+            warn!(util::LOG, "ignoring incomplete transaction at the end of journal {:?}", journal.path);
+            journal.file.set_len(end)?;
+            journal.file.sync_all()?;
+        }
+
+        Ok(journal)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the journal has no records at all, committed or not.
+    pub fn is_empty(&self) -> Result<bool, StorageError> {
+        Ok(self.file.metadata()?.len() <= HEADER_LEN)
+    }
+
+    /// Append `entries` as a single transaction and sync it to disk.
+    pub fn append(&mut self, entries: &[JournalEntry]) -> Result<(), StorageError> {
+        let mut records = Vec::new();
+        for entry in entries {
+            let mut payload = Vec::new();
+            match entry {
+                JournalEntry::Write { position, data } => {
+                    payload.push(KIND_WRITE);
+                    write_position(&mut payload, *position);
+                    payload.extend_from_slice(data);
+                },
+                JournalEntry::Remove(position) => {
+                    payload.push(KIND_REMOVE);
+                    write_position(&mut payload, *position);
+                },
+            }
+            write_record(&mut records, &payload);
+        }
+        write_record(&mut records, &[KIND_COMMIT]);
+
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&records)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Entries of every committed transaction, in the order they were appended.
+    ///
+    /// Reading stops at the first record that is truncated or fails its checksum, dropping the
+    /// transaction it was part of.
+    pub fn committed(&mut self) -> Result<Vec<JournalEntry>, StorageError> {
+        self.scan().map(|(committed, _)| committed)
+    }
+
+    // Committed entries and the offset just past the last commit.
+    fn scan(&mut self) -> Result<(Vec<JournalEntry>, u64), StorageError> {
+        let mut contents = Vec::new();
+        self.file.seek(SeekFrom::Start(HEADER_LEN))?;
+        self.file.read_to_end(&mut contents)?;
+
+        let mut committed = Vec::new();
+        let mut pending = Vec::new();
+        let mut end = HEADER_LEN;
+        let mut rest = &contents[..];
+        while let Some((payload, next)) = read_record(rest) {
+            rest = next;
+            let (kind, body) = match payload.split_first() {
+                Some((kind, body)) => (*kind, body),
+                None => break,
+            };
+
+            match kind {
+                KIND_COMMIT => {
+                    committed.append(&mut pending);
+                    end = HEADER_LEN + (contents.len() - rest.len()) as u64;
+                },
+                KIND_WRITE if body.len() > 12 => pending.push(JournalEntry::Write {
+                    position: read_position(body),
+                    data: body[12..].to_vec(),
+                }),
+                KIND_REMOVE if body.len() == 12 => pending.push(JournalEntry::Remove(read_position(body))),
+                _ => break,
+            }
+        }
+
+        Ok((committed, end))
+    }
+
+    /// Drop every record once they no longer need replaying.
+    pub fn clear(&mut self) -> Result<(), StorageError> {
+        self.file.set_len(HEADER_LEN)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+fn write_record(out: &mut Vec<u8>, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+// The payload of the record at the front of `bytes` and whatever follows it.
+fn read_record(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    if bytes.len() < 8 {
+        return None;
+    }
+
+    let length = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let crc = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let rest = &bytes[8..];
+    if rest.len() < length {
+        return None;
+    }
+
+    let (payload, rest) = rest.split_at(length);
+    if crc32fast::hash(payload) != crc {
+        return None;
+    }
+    Some((payload, rest))
+}
+
+fn write_position(out: &mut Vec<u8>, position: ChunkPosition) {
+    for coordinate in &[position.x(), position.y(), position.z()] {
+        out.extend_from_slice(&coordinate.to_le_bytes());
+    }
+}
+
+fn read_position(bytes: &[u8]) -> ChunkPosition {
+    let coordinate = |at: usize| i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    ChunkPosition::new(coordinate(0), coordinate(4), coordinate(8))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunk::ChunkPosition;
+    use crate::storage::journal::{Journal, JournalEntry};
+
+    #[test]
+    fn transactions() {
+        let path = std::env::temp_dir().join(format!("voxel-journal-{}.vxj", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let first = vec![
+            JournalEntry::Write {
+                position: ChunkPosition::new(-1, 2, 3),
+                data: vec![1, 2, 3],
+            },
+            JournalEntry::Remove(ChunkPosition::new(4, 5, -6)),
+        ];
+        let second = vec![JournalEntry::Write {
+            position: ChunkPosition::new(0, 0, 0),
+            data: vec![9; 100],
+        }];
+
+        let mut journal = Journal::open(&path).unwrap();
+        assert!(journal.is_empty().unwrap());
+        journal.append(&first).unwrap();
+        journal.append(&second).unwrap();
+        drop(journal);
+
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.committed().unwrap(), [&first[..], &second[..]].concat());
+
+        // Every cut through the second transaction keeps only the first.
+        let full = std::fs::read(&path).unwrap();
+        let second_len = (8 + 1 + 12 + 100) + (8 + 1);
+        for cut in 1..=second_len {
+            std::fs::write(&path, &full[..full.len() - cut]).unwrap();
+            let mut journal = Journal::open(&path).unwrap();
+            assert_eq!(journal.committed().unwrap(), first, "cut {}", cut);
+        }
+
+        // The incomplete transaction was dropped, so new ones can still be read.
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&second).unwrap();
+        assert_eq!(journal.committed().unwrap(), [&first[..], &second[..]].concat());
+        std::fs::write(&path, &full).unwrap();
+
+        // As does a flipped bit anywhere in it.
+        let mut corrupt = full.clone();
+        corrupt[full.len() - 50] ^= 0x10;
+        std::fs::write(&path, &corrupt).unwrap();
+        assert_eq!(Journal::open(&path).unwrap().committed().unwrap(), first);
+
+        journal.clear().unwrap();
+        assert!(journal.is_empty().unwrap());
+        assert!(journal.committed().unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! Persisting chunks to disk.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+pub mod chunk;
+pub mod journal;
+pub mod region;
+pub mod world;
+
+pub use self::chunk::{encode_chunk, decode_chunk, read_chunk, Compression, CHUNK_MAGIC, CHUNK_FORMAT_VERSION};
+pub use self::journal::{Journal, JournalEntry};
+pub use self::region::{RegionFile, RegionPosition, region_index, REGION_CHUNKS, SECTOR_SIZE};
+pub use self::world::WorldStorage;
+
+#[derive(Debug)]
+pub enum StorageError {
+    IO(io::Error),
+    /// The data doesn't start with the magic bytes of the expected format.
+    InvalidMagic,
+    /// Written by a newer version of the format.
+    UnsupportedVersion(u16),
+    /// Written with chunk dimensions other than this build's.
+    DimensionMismatch {
+        width: usize,
+        height: usize,
+        length: usize,
+    },
+    UnknownCompression(u8),
+    /// Compressed with a codec whose feature isn't enabled in this build.
+    CompressionUnavailable(Compression),
+    /// A block id that doesn't fit in this build's `BlockSize`.
+    BlockOutOfRange {
+        id: u64,
+        max: usize,
+    },
+    Corrupt(String),
+}
+
+impl StorageError {
+    pub(crate) fn corrupt<S: Into<String>>(reason: S) -> StorageError {
+        StorageError::Corrupt(reason.into())
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(error: io::Error) -> StorageError {
+        StorageError::IO(error)
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::IO(error) => write!(f, "{}", error),
+            StorageError::InvalidMagic => write!(f, "not a chunk file"),
+            StorageError::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+            StorageError::DimensionMismatch { width, height, length } => {
+                write!(f, "chunk dimensions {}x{}x{} don't match this build", width, height, length)
+            },
+            StorageError::UnknownCompression(tag) => write!(f, "unknown compression {}", tag),
+            StorageError::CompressionUnavailable(compression) => {
+                write!(f, "{} compression isn't enabled in this build", compression)
+            },
+            StorageError::BlockOutOfRange { id, max } => {
+                write!(f, "block id {} is larger than max block type {}", id, max)
+            },
+            StorageError::Corrupt(reason) => write!(f, "corrupt data: {}", reason),
+        }
+    }
+}
+
+impl Error for StorageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StorageError::IO(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Append `value` as a LEB128 varint.
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Read a LEB128 varint from the front of `bytes`, advancing past it.
+pub(crate) fn read_varint(bytes: &mut &[u8]) -> Result<u64, StorageError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| StorageError::corrupt("unexpected end of data"))?;
+        *bytes = rest;
+
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(StorageError::corrupt("varint is too long"))
+}
@@ -0,0 +1,650 @@
+//! Region files, which store a 16×16×16 cube of chunks in a single file.
+//!
+//! The file is split into 4 KiB sectors. The first sectors hold a header followed by an offset table
+//! with an entry for every chunk in the region, and each chunk's data (see `storage::chunk`) is
+//! stored in a run of whole sectors after that:
+//!
+//! | bytes     | field                                                         |
+//! |-----------|---------------------------------------------------------------|
+//! | 4         | magic, `VXRG`                                                 |
+//! | 2         | format version                                                |
+//! | 2         | reserved, always 0                                            |
+//! | 8 × 4096  | first sector and length in bytes of each chunk, 0 if missing  |
+//!
+//! Entries are ordered like blocks in a chunk, y then x then z. Rewritten chunks are always moved to
+//! newly allocated sectors, which are synced to disk before the table is updated, and the file is
+//! only freed once the updated table has been synced by `sync` as well. So an interrupted write
+//! leaves the previous version in place. Freed sectors are reused by later writes or cut off the
+//! end of the file, and `compact` removes whatever gaps are left, though it moves chunks in place
+//! and has no such guarantee.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::chunk::{Chunk, ChunkMut, ChunkPosition};
+use crate::storage::{StorageError, Compression, encode_chunk, decode_chunk};
+
+/// Chunks along each axis of a region.
+pub const REGION_CHUNKS: usize = 16;
+pub const REGION_VOLUME: usize = REGION_CHUNKS * REGION_CHUNKS * REGION_CHUNKS;
+pub const SECTOR_SIZE: usize = 4096;
+
+pub const REGION_MAGIC: [u8; 4] = *b"VXRG";
+pub const REGION_FORMAT_VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 8;
+const HEADER_SECTORS: usize = (HEADER_LEN + REGION_VOLUME * ENTRY_LEN).div_ceil(SECTOR_SIZE);
+
+/// Position of a region in the world, measured in regions.
+#[derive(Debug, Copy, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RegionPosition {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+impl RegionPosition {
+    pub const fn new(x: i32, y: i32, z: i32) -> RegionPosition {
+        RegionPosition { x, y, z }
+    }
+
+    /// The region containing `chunk`.
+    pub fn of(chunk: ChunkPosition) -> RegionPosition {
+        let region = |coordinate: i32| coordinate.div_euclid(REGION_CHUNKS as i32);
+        RegionPosition::new(region(chunk.x()), region(chunk.y()), region(chunk.z()))
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn z(&self) -> i32 {
+        self.z
+    }
+
+    /// The chunk at `index` in this region's offset table.
+    pub fn chunk(&self, index: usize) -> ChunkPosition {
+        let size = REGION_CHUNKS as i32;
+        let index = index as i32;
+        ChunkPosition::new(
+            self.x * size + (index / size) % size,
+            self.y * size + index / (size * size),
+            self.z * size + index % size,
+        )
+    }
+
+    /// Name of the region's file, `r.x.y.z.vxr`.
+    pub fn file_name(&self) -> String {
+        format!("r.{}.{}.{}.vxr", self.x, self.y, self.z)
+    }
+
+    pub fn from_file_name(name: &str) -> Option<RegionPosition> {
+        let mut parts = name.strip_prefix("r.")?.strip_suffix(".vxr")?.split('.');
+        let mut coordinate = || parts.next()?.parse::<i32>().ok();
+        let position = RegionPosition::new(coordinate()?, coordinate()?, coordinate()?);
+        match parts.next() {
+            Some(_) => None,
+            None => Some(position),
+        }
+    }
+}
+
+impl fmt::Display for RegionPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}, {})", self.x, self.y, self.z)
+    }
+}
+
+/// Index of `chunk` in the offset table of its region.
+pub fn region_index(chunk: ChunkPosition) -> usize {
+    let local = |coordinate: i32| coordinate.rem_euclid(REGION_CHUNKS as i32) as usize;
+    (local(chunk.y()) * REGION_CHUNKS + local(chunk.x())) * REGION_CHUNKS + local(chunk.z())
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+struct Entry {
+    sector: u32,
+    length: u32,
+}
+
+impl Entry {
+    fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn sectors(&self) -> usize {
+        sectors_for(self.length as usize)
+    }
+
+    fn range(&self) -> std::ops::Range<usize> {
+        self.sector as usize..self.sector as usize + self.sectors()
+    }
+}
+
+fn sectors_for(length: usize) -> usize {
+    length.div_ceil(SECTOR_SIZE)
+}
+
+#[derive(Debug)]
+pub struct RegionFile {
+    path: PathBuf,
+    file: File,
+    entries: Box<[Entry]>,
+    // Whether each sector of the file is in use, header included.
+    used: Vec<bool>,
+    // Entries replaced since the last sync. The table on disk may still point at their sectors, so
+    // they stay in use until it has been synced.
+    released: Vec<Entry>,
+}
+
+impl RegionFile {
+    /// Open the region file at `path`, creating an empty one if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<RegionFile, StorageError> {
+        RegionFile::open_with(path.as_ref(), false)
+    }
+
+    /// Open a region file that may have been damaged by a crash, such as one cut short in the middle
+    /// of a write.
+    ///
+    /// A header that is only partly there is replaced by an empty one, and entries pointing outside
+    /// of the file or into the sectors of another chunk are removed. Chunks lost that way have to be
+    /// written again, which `WorldStorage` does by replaying its journal.
+    pub fn recover<P: AsRef<Path>>(path: P) -> Result<RegionFile, StorageError> {
+        RegionFile::open_with(path.as_ref(), true)
+    }
+
+    fn open_with(path: &Path, lenient: bool) -> Result<RegionFile, StorageError> {
+        let path = path.to_owned();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let mut length = file.metadata()?.len() as usize;
+
+        if lenient && length > 0 && length < HEADER_LEN + REGION_VOLUME * ENTRY_LEN {
+            warn!(util::LOG, "replacing the incomplete header of region file {:?}", path);
+            file.set_len(0)?;
+            length = 0;
+        }
+
+        if length == 0 {
+            let mut header = vec![0u8; HEADER_SECTORS * SECTOR_SIZE];
+            header[0..4].copy_from_slice(&REGION_MAGIC);
+            header[4..6].copy_from_slice(&REGION_FORMAT_VERSION.to_le_bytes());
+            file.write_all(&header)?;
+            file.sync_all()?;
+
+            return Ok(RegionFile {
+                path,
+                file,
+                entries: vec![Entry::default(); REGION_VOLUME].into_boxed_slice(),
+                used: vec![true; HEADER_SECTORS],
+                released: Vec::new(),
+            });
+        }
+
+        let mut header = vec![0u8; HEADER_LEN + REGION_VOLUME * ENTRY_LEN];
+        if length < header.len() {
+            return Err(StorageError::corrupt(format!("header is only {} bytes long", length)));
+        }
+        file.read_exact(&mut header)?;
+        if header[0..4] != REGION_MAGIC {
+            return Err(StorageError::InvalidMagic);
+        }
+
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version > REGION_FORMAT_VERSION {
+            return Err(StorageError::UnsupportedVersion(version));
+        }
+
+        let sectors = sectors_for(length).max(HEADER_SECTORS);
+        let mut used = vec![false; sectors];
+        used[..HEADER_SECTORS].iter_mut().for_each(|used| *used = true);
+
+        let mut entries = Vec::with_capacity(REGION_VOLUME);
+        let mut dropped = Vec::new();
+        for (index, bytes) in header[HEADER_LEN..].chunks(ENTRY_LEN).enumerate() {
+            let entry = Entry {
+                sector: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                length: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            };
+
+            if !entry.is_empty() {
+                let range = entry.range();
+                let end = entry.sector as usize * SECTOR_SIZE + entry.length as usize;
+                let problem = if range.start < HEADER_SECTORS || end > length {
+                    Some("is outside of the file")
+                } else if used[range.clone()].iter().any(|used| *used) {
+                    Some("overlaps another chunk")
+                } else {
+                    None
+                };
+
+                match problem {
+                    Some(problem) if lenient => {
+                        warn!(util::LOG, "dropping chunk {} of region file {:?}, it {}", index, path, problem);
+                        dropped.push(index);
+                        entries.push(Entry::default());
+                        continue;
+                    },
+                    Some(problem) => return Err(StorageError::corrupt(format!("chunk {} {}", index, problem))),
+                    None => used[range].iter_mut().for_each(|used| *used = true),
+                }
+            }
+
+            entries.push(entry);
+        }
+
+        let mut region = RegionFile {
+            path,
+            file,
+            entries: entries.into_boxed_slice(),
+            used,
+            released: Vec::new(),
+        };
+        for index in dropped {
+            region.set_entry(index, Entry::default())?;
+        }
+        Ok(region)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the region has data for `chunk`.
+    ///
+    /// Only the chunk's position within its region is used, like every other method taking one.
+    pub fn contains(&self, chunk: ChunkPosition) -> bool {
+        !self.entries[region_index(chunk)].is_empty()
+    }
+
+    /// Number of chunks stored.
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|entry| !entry.is_empty()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Table indices of the chunks stored, see `RegionPosition::chunk`.
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.entries.iter().enumerate().filter(|(_, entry)| !entry.is_empty()).map(|(index, _)| index)
+    }
+
+    /// Sectors in the file, header and free sectors included.
+    pub fn sectors(&self) -> usize {
+        self.used.len()
+    }
+
+    /// Sectors that aren't used by any chunk and can be reused or reclaimed by compacting.
+    ///
+    /// Sectors of replaced or removed chunks only count once the region has been synced.
+    pub fn free_sectors(&self) -> usize {
+        self.used.iter().filter(|used| !**used).count()
+    }
+
+    /// The stored data of `chunk` as written by `encode_chunk`.
+    pub fn read(&mut self, chunk: ChunkPosition) -> Result<Option<Vec<u8>>, StorageError> {
+        let entry = self.entries[region_index(chunk)];
+        if entry.is_empty() {
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; entry.length as usize];
+        self.file.seek(SeekFrom::Start((entry.sector as usize * SECTOR_SIZE) as u64))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    pub fn load_chunk<C: ChunkMut + Default>(&mut self, chunk: ChunkPosition) -> Result<Option<C>, StorageError> {
+        match self.read(chunk)? {
+            Some(data) => decode_chunk(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Store `data` as the data of `chunk`, replacing what was there.
+    pub fn write(&mut self, chunk: ChunkPosition, data: &[u8]) -> Result<(), StorageError> {
+        if data.is_empty() {
+            return Err(StorageError::corrupt("chunk data is empty"));
+        }
+
+        let index = region_index(chunk);
+        let previous = self.entries[index];
+
+        let sectors = sectors_for(data.len());
+        let sector = self.allocate(sectors);
+        let mut padded = data.to_vec();
+        padded.resize(sectors * SECTOR_SIZE, 0);
+        self.file.seek(SeekFrom::Start((sector * SECTOR_SIZE) as u64))?;
+        self.file.write_all(&padded)?;
+        // The table must never point at sectors that aren't on disk yet.
+        self.file.sync_data()?;
+
+        self.set_entry(index, Entry {
+            sector: sector as u32,
+            length: data.len() as u32,
+        })?;
+        self.release(previous);
+        Ok(())
+    }
+
+    pub fn save_chunk<C: Chunk + ?Sized>(
+        &mut self,
+        position: ChunkPosition,
+        chunk: &C,
+        compression: Compression,
+    ) -> Result<(), StorageError> {
+        let data = encode_chunk(chunk, compression)?;
+        self.write(position, &data)
+    }
+
+    /// Remove `chunk` from the region, returning whether it was stored.
+    pub fn remove(&mut self, chunk: ChunkPosition) -> Result<bool, StorageError> {
+        let index = region_index(chunk);
+        let previous = self.entries[index];
+        if previous.is_empty() {
+            return Ok(false);
+        }
+
+        self.set_entry(index, Entry::default())?;
+        self.release(previous);
+        Ok(true)
+    }
+
+    /// Move every chunk to the start of the file so no free sectors are left, returning how many
+    /// sectors were reclaimed.
+    pub fn compact(&mut self) -> Result<usize, StorageError> {
+        self.sync()?;
+        let before = self.sectors();
+
+        let mut stored: Vec<(usize, Entry)> = self.indices().map(|index| (index, self.entries[index])).collect();
+        stored.sort_by_key(|(_, entry)| entry.sector);
+
+        let mut next = HEADER_SECTORS;
+        for (index, entry) in stored {
+            if entry.sector as usize != next {
+                let mut data = vec![0u8; entry.sectors() * SECTOR_SIZE];
+                self.file.seek(SeekFrom::Start((entry.sector as usize * SECTOR_SIZE) as u64))?;
+                self.file.read_exact(&mut data)?;
+                self.file.seek(SeekFrom::Start((next * SECTOR_SIZE) as u64))?;
+                self.file.write_all(&data)?;
+                self.file.sync_data()?;
+                self.set_entry(index, Entry {
+                    sector: next as u32,
+                    ..entry
+                })?;
+            }
+            next += entry.sectors();
+        }
+
+        self.used = vec![true; next];
+        self.file.sync_data()?;
+        self.file.set_len((next * SECTOR_SIZE) as u64)?;
+        Ok(before - next)
+    }
+
+    /// Flush everything written so far to disk, then free the sectors of the chunks replaced or
+    /// removed since the last sync.
+    pub fn sync(&mut self) -> Result<(), StorageError> {
+        self.file.sync_all()?;
+        if self.released.is_empty() {
+            return Ok(());
+        }
+
+        for entry in std::mem::take(&mut self.released) {
+            self.used[entry.range()].iter_mut().for_each(|used| *used = false);
+        }
+        let end = self.used.iter().rposition(|used| *used).map_or(HEADER_SECTORS, |last| last + 1);
+        if end < self.used.len() {
+            self.used.truncate(end);
+            self.file.set_len((end * SECTOR_SIZE) as u64)?;
+        }
+        Ok(())
+    }
+
+    // First run of `count` free sectors, growing the file if there isn't one.
+    fn allocate(&mut self, count: usize) -> usize {
+        let mut start = HEADER_SECTORS;
+        let mut run = 0;
+        for (sector, used) in self.used.iter().enumerate().skip(HEADER_SECTORS) {
+            if *used {
+                start = sector + 1;
+                run = 0;
+            } else {
+                run += 1;
+                if run == count {
+                    break;
+                }
+            }
+        }
+
+        if self.used.len() < start + count {
+            self.used.resize(start + count, false);
+        }
+        self.used[start..start + count].iter_mut().for_each(|used| *used = true);
+        start
+    }
+
+    // Free the sectors of a replaced entry at the next sync.
+    fn release(&mut self, entry: Entry) {
+        if !entry.is_empty() {
+            self.released.push(entry);
+        }
+    }
+
+    fn set_entry(&mut self, index: usize, entry: Entry) -> Result<(), StorageError> {
+        let mut bytes = [0u8; ENTRY_LEN];
+        bytes[0..4].copy_from_slice(&entry.sector.to_le_bytes());
+        bytes[4..8].copy_from_slice(&entry.length.to_le_bytes());
+        self.file.seek(SeekFrom::Start((HEADER_LEN + index * ENTRY_LEN) as u64))?;
+        self.file.write_all(&bytes)?;
+        self.entries[index] = entry;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::Block;
+    use crate::chunk::{Chunk, ChunkMut, BoxedChunk, ChunkPosition, LocalBlockPosition};
+    use crate::storage::{Compression, StorageError};
+    use crate::storage::region::{RegionFile, RegionPosition, region_index, HEADER_SECTORS};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("voxel-region-{}-{}.vxr", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    // Chunk that takes several sectors, different for every seed.
+    fn noisy(seed: usize) -> BoxedChunk {
+        let mut chunk = BoxedChunk::empty();
+        for index in 0..5000 {
+            let value = (index * 7919 + seed * 104_729) % 262_139;
+            let position = LocalBlockPosition::unchecked_new(value % 64, (value / 64) % 64, (value / 4096) % 64);
+            chunk.set_block(&position, Block::hard_create((value % 200) as _));
+        }
+        chunk
+    }
+
+    fn same(a: &dyn Chunk, b: &dyn Chunk) -> bool {
+        (0..64 * 64 * 64).all(|index| {
+            let position = LocalBlockPosition::unchecked_new((index / 64) % 64, index / 4096, index % 64);
+            a.block(&position) == b.block(&position)
+        })
+    }
+
+    #[test]
+    fn positions() {
+        let chunk = ChunkPosition::new(-1, 17, 5);
+        let region = RegionPosition::of(chunk);
+        assert_eq!(region, RegionPosition::new(-1, 1, 0));
+        assert_eq!(region.chunk(region_index(chunk)), chunk);
+        assert_eq!(region_index(ChunkPosition::new(0, 0, 1)), 1);
+        assert_eq!(region_index(ChunkPosition::new(1, 0, 0)), 16);
+        assert_eq!(region_index(ChunkPosition::new(0, 1, 0)), 256);
+
+        assert_eq!(RegionPosition::from_file_name(&region.file_name()), Some(region));
+        assert_eq!(RegionPosition::from_file_name("r.1.2.vxr"), None);
+        assert_eq!(RegionPosition::from_file_name("r.1.2.3.4.vxr"), None);
+        assert_eq!(RegionPosition::from_file_name("mapping.json"), None);
+    }
+
+    #[test]
+    fn read_write() {
+        let path = temp_path("read-write");
+        let mut region = RegionFile::open(&path).unwrap();
+        let a = ChunkPosition::new(0, 0, 0);
+        let b = ChunkPosition::new(3, 15, 7);
+        assert!(region.load_chunk::<BoxedChunk>(a).unwrap().is_none());
+
+        region.save_chunk(a, &noisy(1), Compression::None).unwrap();
+        region.save_chunk(b, &BoxedChunk::flat(Block::hard_create(4), 2), Compression::None).unwrap();
+        assert_eq!(region.len(), 2);
+        assert!(region.contains(b) && !region.contains(ChunkPosition::new(1, 0, 0)));
+
+        // Reopening reads the offset table back.
+        drop(region);
+        let mut region = RegionFile::open(&path).unwrap();
+        assert!(same(&noisy(1), &region.load_chunk::<BoxedChunk>(a).unwrap().unwrap()));
+        assert!(same(&BoxedChunk::flat(Block::hard_create(4), 2), &region.load_chunk::<BoxedChunk>(b).unwrap().unwrap()));
+        assert_eq!(region.indices().collect::<Vec<_>>(), vec![region_index(a), region_index(b)]);
+
+        assert!(region.remove(a).unwrap());
+        assert!(!region.remove(a).unwrap());
+        assert!(region.load_chunk::<BoxedChunk>(a).unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn free_space() {
+        let path = temp_path("free-space");
+        let mut region = RegionFile::open(&path).unwrap();
+        let positions: Vec<_> = (0..4).map(|z| ChunkPosition::new(0, 0, z)).collect();
+        for (seed, position) in positions.iter().enumerate() {
+            region.save_chunk(*position, &noisy(seed), Compression::None).unwrap();
+        }
+        let full = region.sectors();
+        assert_eq!(region.free_sectors(), 0);
+
+        // Freed sectors are reused instead of growing the file.
+        region.remove(positions[1]).unwrap();
+        region.sync().unwrap();
+        let freed = region.free_sectors();
+        assert!(freed > 0);
+        region.save_chunk(positions[1], &BoxedChunk::empty(), Compression::None).unwrap();
+        assert_eq!(region.sectors(), full);
+        assert_eq!(region.free_sectors(), freed - 1);
+
+        // Removing the last chunk truncates the file.
+        region.remove(positions[3]).unwrap();
+        region.sync().unwrap();
+        assert!(region.sectors() < full);
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, region.sectors() * 4096);
+
+        let reclaimed = region.compact().unwrap();
+        assert_eq!(reclaimed, freed - 1);
+        assert_eq!(region.free_sectors(), 0);
+        drop(region);
+
+        let mut region = RegionFile::open(&path).unwrap();
+        assert!(same(&noisy(0), &region.load_chunk::<BoxedChunk>(positions[0]).unwrap().unwrap()));
+        assert!(same(&BoxedChunk::empty(), &region.load_chunk::<BoxedChunk>(positions[1]).unwrap().unwrap()));
+        assert!(same(&noisy(2), &region.load_chunk::<BoxedChunk>(positions[2]).unwrap().unwrap()));
+        assert!(region.load_chunk::<BoxedChunk>(positions[3]).unwrap().is_none());
+
+        // Everything gone leaves just the header.
+        for position in &positions {
+            region.remove(*position).unwrap();
+        }
+        region.sync().unwrap();
+        assert_eq!(region.sectors(), HEADER_SECTORS);
+        assert!(region.is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn no_reuse_before_sync() {
+        let path = temp_path("no-reuse");
+        let mut region = RegionFile::open(&path).unwrap();
+        let (a, b, c) = (ChunkPosition::new(0, 0, 0), ChunkPosition::new(0, 0, 1), ChunkPosition::new(0, 0, 2));
+        region.save_chunk(a, &noisy(0), Compression::None).unwrap();
+        region.sync().unwrap();
+        let old = region.entries[region_index(a)];
+        let stored = region.read(a).unwrap().unwrap();
+
+        // Until the table pointing at the old version is synced, its sectors are left alone.
+        region.save_chunk(a, &noisy(1), Compression::None).unwrap();
+        region.save_chunk(b, &noisy(2), Compression::None).unwrap();
+        region.remove(b).unwrap();
+        region.save_chunk(c, &noisy(3), Compression::None).unwrap();
+        assert_eq!(region.free_sectors(), 0);
+        for position in &[a, c] {
+            let range = region.entries[region_index(*position)].range();
+            assert!(range.start >= old.range().end || range.end <= old.range().start);
+        }
+        let bytes = std::fs::read(&path).unwrap();
+        let start = old.sector as usize * 4096;
+        assert_eq!(&bytes[start..start + stored.len()], &stored[..]);
+
+        region.sync().unwrap();
+        assert!(region.free_sectors() >= old.sectors());
+        region.save_chunk(b, &noisy(4), Compression::None).unwrap();
+        assert_eq!(region.entries[region_index(b)].sector, old.sector);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_table() {
+        let path = temp_path("corrupt");
+        let mut region = RegionFile::open(&path).unwrap();
+        region.save_chunk(ChunkPosition::new(0, 0, 0), &noisy(0), Compression::None).unwrap();
+        drop(region);
+
+        // Point the second entry into the first chunk's sectors.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let first = bytes[8..16].to_vec();
+        bytes[16..24].copy_from_slice(&first);
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(RegionFile::open(&path).unwrap_err(), StorageError::Corrupt(_)));
+
+        bytes[0] = b'X';
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(RegionFile::open(&path).unwrap_err(), StorageError::InvalidMagic));
+
+        // A header cut short while it was being written.
+        std::fs::write(&path, &bytes[..10]).unwrap();
+        assert!(matches!(RegionFile::open(&path).unwrap_err(), StorageError::Corrupt(_)));
+        let region = RegionFile::recover(&path).unwrap();
+        assert!(region.is_empty());
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, HEADER_SECTORS * 4096);
+
+        // Recovering drops the chunks that are damaged and keeps the others.
+        let mut region = RegionFile::open(&path).unwrap();
+        let (first, second) = (ChunkPosition::new(0, 0, 0), ChunkPosition::new(0, 0, 1));
+        region.save_chunk(first, &noisy(0), Compression::None).unwrap();
+        region.save_chunk(second, &noisy(1), Compression::None).unwrap();
+        drop(region);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 4096]).unwrap();
+        assert!(matches!(RegionFile::open(&path).unwrap_err(), StorageError::Corrupt(_)));
+
+        let mut region = RegionFile::recover(&path).unwrap();
+        assert!(same(&noisy(0), &region.load_chunk::<BoxedChunk>(first).unwrap().unwrap()));
+        assert!(!region.contains(second));
+        drop(region);
+        assert_eq!(RegionFile::open(&path).unwrap().len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
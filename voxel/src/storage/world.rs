@@ -0,0 +1,355 @@
+//! A world kept on disk as a directory of region files.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::block::IdMapping;
+use crate::chunk::{Chunk, ChunkMut, ChunkPosition};
+use crate::storage::{StorageError, Compression, encode_chunk};
+use crate::storage::journal::{Journal, JournalEntry};
+use crate::storage::region::{RegionFile, RegionPosition, SECTOR_SIZE};
+
+const MAPPING_FILE: &str = "mapping.json";
+const JOURNAL_FILE: &str = "journal.vxj";
+
+/// Chunks of a world, stored in region files under a single directory.
+///
+/// Region files are opened the first time one of their chunks is used and kept open afterwards.
+/// Reading a chunk of a region that doesn't exist yet doesn't create its file.
+///
+/// Saving and removing chunks goes through a `Journal` first, so every call either happens
+/// completely or not at all even if the process is killed halfway through.
+#[derive(Debug)]
+pub struct WorldStorage {
+    root: PathBuf,
+    compression: Compression,
+    regions: HashMap<RegionPosition, RegionFile>,
+    journal: Journal,
+}
+
+impl WorldStorage {
+    /// Open the world stored in `root`, creating the directory if needed.
+    ///
+    /// Writes that were interrupted by a crash are finished by replaying the journal.
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<WorldStorage, StorageError> {
+        let root = root.as_ref().to_owned();
+        std::fs::create_dir_all(&root)?;
+        let journal = Journal::open(root.join(JOURNAL_FILE))?;
+
+        let mut world = WorldStorage {
+            root,
+            compression: Compression::default(),
+            regions: HashMap::new(),
+            journal,
+        };
+
+        let entries = world.journal.committed()?;
+        if !entries.is_empty() {
+            warn!(util::LOG, "replaying {} chunk writes from the journal of {:?}", entries.len(), world.root);
+
+            // The crash that left the journal behind may have damaged the regions it was writing to.
+            for entry in &entries {
+                let region = RegionPosition::of(entry.position());
+                if !world.regions.contains_key(&region) {
+                    let file = RegionFile::recover(world.region_path(region))?;
+                    world.regions.insert(region, file);
+                }
+            }
+            world.apply(&entries)?;
+        }
+        world.journal.clear()?;
+
+        Ok(world)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Compression used for chunks saved from now on, chunks already saved are read either way.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    pub fn region_path(&self, region: RegionPosition) -> PathBuf {
+        self.root.join(region.file_name())
+    }
+
+    /// Regions that have a file on disk.
+    pub fn regions(&self) -> Result<Vec<RegionPosition>, StorageError> {
+        let mut regions = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let name = entry?.file_name();
+            if let Some(region) = name.to_str().and_then(RegionPosition::from_file_name) {
+                regions.push(region);
+            }
+        }
+        regions.sort();
+        Ok(regions)
+    }
+
+    pub fn contains_chunk(&mut self, position: ChunkPosition) -> Result<bool, StorageError> {
+        Ok(self.region(RegionPosition::of(position), false)?.is_some_and(|region| region.contains(position)))
+    }
+
+    pub fn load_chunk<C: ChunkMut + Default>(&mut self, position: ChunkPosition) -> Result<Option<C>, StorageError> {
+        match self.region(RegionPosition::of(position), false)? {
+            Some(region) => region.load_chunk(position),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_chunk<C: Chunk + ?Sized>(&mut self, position: ChunkPosition, chunk: &C) -> Result<(), StorageError> {
+        let data = encode_chunk(chunk, self.compression)?;
+        self.commit(&[JournalEntry::Write { position, data }])
+    }
+
+    /// Save several chunks at once, either all of them or none if the process is interrupted.
+    pub fn save_chunks<'a, I>(&mut self, chunks: I) -> Result<(), StorageError>
+    where
+        I: IntoIterator<Item = (ChunkPosition, &'a dyn Chunk)>,
+    {
+        let mut entries = Vec::new();
+        for (position, chunk) in chunks {
+            let data = encode_chunk(chunk, self.compression)?;
+            entries.push(JournalEntry::Write { position, data });
+        }
+        self.commit(&entries)
+    }
+
+    /// Remove a chunk, returning whether it was stored.
+    pub fn remove_chunk(&mut self, position: ChunkPosition) -> Result<bool, StorageError> {
+        if !self.contains_chunk(position)? {
+            return Ok(false);
+        }
+
+        self.commit(&[JournalEntry::Remove(position)])?;
+        Ok(true)
+    }
+
+    /// Compact every region file, returning how many bytes were reclaimed.
+    ///
+    /// Compacting isn't journaled, so it should only run when the world isn't in use.
+    pub fn compact(&mut self) -> Result<u64, StorageError> {
+        let mut reclaimed = 0;
+        for region in self.regions()? {
+            let sectors = self.region_file(region)?.compact()?;
+            reclaimed += (sectors * SECTOR_SIZE) as u64;
+        }
+
+        if reclaimed > 0 {
+            info!(util::LOG, "compacted world {:?}, reclaimed {} bytes", self.root, reclaimed);
+        }
+        Ok(reclaimed)
+    }
+
+    /// Flush every open region file to disk.
+    pub fn sync(&mut self) -> Result<(), StorageError> {
+        for region in self.regions.values_mut() {
+            region.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Block ids of the registry chunks were saved with, see `BlockRemapper`.
+    pub fn load_mapping(&self) -> Result<Option<IdMapping>, StorageError> {
+        let path = self.root.join(MAPPING_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read(&path)?;
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| StorageError::corrupt(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Save the block ids chunks are saved with, replacing the previous mapping in one step so a
+    /// crash leaves either the old or the new one.
+    pub fn save_mapping(&self, mapping: &IdMapping) -> Result<(), StorageError> {
+        let contents = serde_json::to_vec_pretty(mapping).map_err(io::Error::other)?;
+        let path = self.root.join(MAPPING_FILE);
+        let temporary = path.with_extension("json.tmp");
+
+        let mut file = File::create(&temporary)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+
+    // Journal `entries`, then write them to the region files and clear the journal again.
+    fn commit(&mut self, entries: &[JournalEntry]) -> Result<(), StorageError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        self.journal.append(entries)?;
+        self.apply(entries)?;
+        self.journal.clear()
+    }
+
+    // Write `entries` to the region files and sync every file they touched.
+    fn apply(&mut self, entries: &[JournalEntry]) -> Result<(), StorageError> {
+        let mut touched = HashSet::new();
+        for entry in entries {
+            let region = RegionPosition::of(entry.position());
+            let file = self.region_file(region)?;
+            match entry {
+                JournalEntry::Write { position, data } => file.write(*position, data)?,
+                JournalEntry::Remove(position) => {
+                    file.remove(*position)?;
+                },
+            }
+            touched.insert(region);
+        }
+
+        for region in touched {
+            self.regions.get_mut(&region).expect("touched regions are open").sync()?;
+        }
+        Ok(())
+    }
+
+    fn region_file(&mut self, region: RegionPosition) -> Result<&mut RegionFile, StorageError> {
+        Ok(self.region(region, true)?.expect("region files are created when missing"))
+    }
+
+    fn region(&mut self, region: RegionPosition, create: bool) -> Result<Option<&mut RegionFile>, StorageError> {
+        if !self.regions.contains_key(&region) {
+            let path = self.region_path(region);
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            self.regions.insert(region, RegionFile::open(path)?);
+        }
+
+        Ok(self.regions.get_mut(&region))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::{Block, BlockRegistry, IdMapping};
+    use crate::chunk::{Chunk, BoxedChunk, PalettedChunk, ChunkPosition, LocalBlockPosition};
+    use crate::storage::{Compression, Journal, JournalEntry, encode_chunk};
+    use crate::storage::region::RegionPosition;
+    use crate::storage::world::WorldStorage;
+
+    fn temp_root(name: &str) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!("voxel-world-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn world_storage() {
+        let root = temp_root("storage");
+        let mut world = WorldStorage::open(&root).unwrap();
+        let near = ChunkPosition::new(0, 0, 0);
+        let far = ChunkPosition::new(-20, 3, 40);
+        assert!(world.load_chunk::<BoxedChunk>(far).unwrap().is_none());
+        assert!(!world.remove_chunk(far).unwrap());
+        assert!(world.regions().unwrap().is_empty());
+
+        world.set_compression(*Compression::ALL.iter().rev().find(|c| c.is_available()).unwrap());
+        world.save_chunk(near, &BoxedChunk::flat(Block::hard_create(1), 0)).unwrap();
+        world.save_chunk(far, &PalettedChunk::flat(Block::hard_create(2), 63)).unwrap();
+        assert_eq!(world.regions().unwrap(), vec![RegionPosition::new(-2, 0, 2), RegionPosition::new(0, 0, 0)]);
+
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        world.save_mapping(&IdMapping::from_registry(&registry)).unwrap();
+        world.sync().unwrap();
+        drop(world);
+
+        let mut world = WorldStorage::open(&root).unwrap();
+        assert!(world.load_mapping().unwrap().unwrap().matches(&registry));
+        assert!(!root.join("mapping.json.tmp").exists());
+        assert!(world.contains_chunk(far).unwrap());
+        let chunk = world.load_chunk::<PalettedChunk>(far).unwrap().unwrap();
+        assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(0, 63, 0)), Block::hard_create(2));
+        let chunk = world.load_chunk::<BoxedChunk>(near).unwrap().unwrap();
+        assert_eq!(chunk.block(&LocalBlockPosition::unchecked_new(0, 0, 0)), Block::hard_create(1));
+
+        assert!(world.remove_chunk(near).unwrap());
+        world.compact().unwrap();
+        assert!(world.load_chunk::<BoxedChunk>(near).unwrap().is_none());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn crash_recovery() {
+        let root = temp_root("crash");
+        let position = ChunkPosition::new(1, 2, 3);
+        let other = ChunkPosition::new(1, 2, 4);
+        let old = BoxedChunk::flat(Block::hard_create(1), 0);
+        let new = BoxedChunk::flat(Block::hard_create(2), 5);
+        let region_path = root.join(RegionPosition::of(position).file_name());
+        let journal_path = root.join("journal.vxj");
+
+        let mut world = WorldStorage::open(&root).unwrap();
+        world.save_chunk(position, &old).unwrap();
+        drop(world);
+        let saved = std::fs::read(&region_path).unwrap();
+        let block = |world: &mut WorldStorage, position| {
+            let chunk = world.load_chunk::<BoxedChunk>(position).unwrap()?;
+            Some(chunk.block(&LocalBlockPosition::unchecked_new(0, 5, 0)).id())
+        };
+
+        // Killed while writing the journal: the transaction is dropped as a whole.
+        let data = encode_chunk(&new, Compression::None).unwrap();
+        let entries = vec![
+            JournalEntry::Write { position, data: data.clone() },
+            JournalEntry::Write { position: other, data: data.clone() },
+        ];
+        Journal::open(&journal_path).unwrap().append(&entries).unwrap();
+        let journal = std::fs::read(&journal_path).unwrap();
+        std::fs::write(&journal_path, &journal[..journal.len() - 20]).unwrap();
+
+        let mut world = WorldStorage::open(&root).unwrap();
+        assert_eq!(block(&mut world, position), Some(0));
+        assert_eq!(block(&mut world, other), None);
+        drop(world);
+        assert_eq!(std::fs::read(&region_path).unwrap(), saved);
+
+        // Killed while writing the region file, after the journal was synced: the write is replayed.
+        std::fs::write(&journal_path, &journal).unwrap();
+        let mut region = saved.clone();
+        region.extend_from_slice(&data[..100]);
+        std::fs::write(&region_path, &region).unwrap();
+
+        let mut world = WorldStorage::open(&root).unwrap();
+        assert_eq!(block(&mut world, position), Some(2));
+        assert_eq!(block(&mut world, other), Some(2));
+        drop(world);
+
+        // Killed after the region file was cut short, by one sector or into its header: the damaged
+        // region is recovered and the write replayed.
+        for cut in &[4096, 2 * 4096, saved.len() - 10] {
+            std::fs::write(&journal_path, &journal).unwrap();
+            std::fs::write(&region_path, &saved[..saved.len() - cut]).unwrap();
+
+            let mut world = WorldStorage::open(&root).unwrap();
+            assert_eq!(block(&mut world, position), Some(2), "cut {}", cut);
+            assert_eq!(block(&mut world, other), Some(2), "cut {}", cut);
+            drop(world);
+        }
+
+        // The journal was cleared, so opening again changes nothing.
+        let region = std::fs::read(&region_path).unwrap();
+        let mut world = WorldStorage::open(&root).unwrap();
+        assert_eq!(block(&mut world, position), Some(2));
+        drop(world);
+        assert_eq!(std::fs::read(&region_path).unwrap(), region);
+        assert_eq!(std::fs::metadata(&journal_path).unwrap().len(), 8);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
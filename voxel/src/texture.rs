@@ -0,0 +1,536 @@
+//! CPU side texture atlas of the textures blocks declare.
+//!
+//! `AtlasBuilder` loads every PNG the registry's block faces reference, packs them into a single
+//! RGBA image and records where each one ended up, so meshers can look up the UV rectangle of any
+//! block face. The layout only depends on the names and sizes of the textures, so the same inputs
+//! always produce the same atlas.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, BlockSize, BlockRegistry};
+use crate::mesh::Face;
+
+pub const DEFAULT_MAX_ATLAS_SIZE: u32 = 4096;
+
+// Top left corner of each texture's padded rectangle in the atlas.
+type Placements = Vec<(String, (u32, u32))>;
+
+#[derive(Debug)]
+pub enum TextureError {
+    IO {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Decode {
+        path: PathBuf,
+        error: png::DecodingError,
+    },
+    Encode {
+        path: PathBuf,
+        error: png::EncodingError,
+    },
+    /// The textures don't fit into an atlas of the maximum size.
+    AtlasFull {
+        max_size: u32,
+    },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::IO { path, error } => write!(f, "{}: {}", path.display(), error),
+            TextureError::Decode { path, error } => write!(f, "{}: invalid PNG: {}", path.display(), error),
+            TextureError::Encode { path, error } => write!(f, "{}: failed to write PNG: {}", path.display(), error),
+            TextureError::AtlasFull { max_size } => {
+                write!(f, "textures don't fit into a {0}x{0} atlas", max_size)
+            },
+        }
+    }
+}
+
+impl Error for TextureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TextureError::IO { error, .. } => Some(error),
+            TextureError::Decode { error, .. } => Some(error),
+            TextureError::Encode { error, .. } => Some(error),
+            TextureError::AtlasFull { .. } => None,
+        }
+    }
+}
+
+/// 8 bit RGBA image, rows from top to bottom.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RgbaImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Fully transparent image.
+    pub fn new(width: u32, height: u32) -> RgbaImage {
+        RgbaImage {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn filled(width: u32, height: u32, pixel: [u8; 4]) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+        for chunk in image.pixels.chunks_mut(4) {
+            chunk.copy_from_slice(&pixel);
+        }
+        image
+    }
+
+    /// `None` if `pixels` isn't `width * height * 4` bytes long.
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<u8>) -> Option<RgbaImage> {
+        if pixels.len() != width as usize * height as usize * 4 {
+            return None;
+        }
+
+        Some(RgbaImage { width, height, pixels })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height, "pixel ({}, {}) is outside of the image", x, y);
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let index = self.index(x, y);
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.pixels[index..index + 4]);
+        pixel
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let index = self.index(x, y);
+        self.pixels[index..index + 4].copy_from_slice(&pixel);
+    }
+
+    /// Decode a PNG of any color type, converting it to 8 bit RGBA.
+    pub fn read_png<R: io::Read>(reader: R) -> Result<RgbaImage, png::DecodingError> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer)?;
+
+        let pixel_count = info.width as usize * info.height as usize;
+        let pixels = match reader.output_color_type().0 {
+            png::ColorType::RGBA => buffer[..pixel_count * 4].to_vec(),
+            png::ColorType::RGB => buffer[..pixel_count * 3]
+                .chunks(3)
+                .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer[..pixel_count * 2]
+                .chunks(2)
+                .flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer[..pixel_count]
+                .iter()
+                .flat_map(|g| vec![*g, *g, *g, 255])
+                .collect(),
+            png::ColorType::Indexed => return Err(png::DecodingError::Format("palette wasn't expanded".into())),
+        };
+
+        Ok(RgbaImage {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    pub fn write_png<W: io::Write>(&self, writer: W) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<RgbaImage, TextureError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|error| TextureError::IO {
+            path: path.to_owned(),
+            error,
+        })?;
+
+        RgbaImage::read_png(io::BufReader::new(file)).map_err(|error| TextureError::Decode {
+            path: path.to_owned(),
+            error,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), TextureError> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|error| TextureError::IO {
+            path: path.to_owned(),
+            error,
+        })?;
+
+        self.write_png(io::BufWriter::new(file)).map_err(|error| TextureError::Encode {
+            path: path.to_owned(),
+            error,
+        })
+    }
+
+    /// Copy `image` to `(x, y)`, repeating its edge pixels `padding` times around it so sampling
+    /// near the edge doesn't bleed into neighbouring textures.
+    fn blit(&mut self, image: &RgbaImage, x: u32, y: u32, padding: u32) {
+        let padding = padding as i64;
+        for dy in -padding..image.height as i64 + padding {
+            for dx in -padding..image.width as i64 + padding {
+                let source_x = dx.max(0).min(image.width as i64 - 1) as u32;
+                let source_y = dy.max(0).min(image.height as i64 - 1) as u32;
+                let target_x = (x as i64 + padding + dx) as u32;
+                let target_y = (y as i64 + padding + dy) as u32;
+                self.set_pixel(target_x, target_y, image.pixel(source_x, source_y));
+            }
+        }
+    }
+}
+
+/// Texture coordinates of a rectangle in the atlas, `(0, 0)` being its top left corner.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+/// Pixel rectangle of a texture in the atlas, excluding its padding.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AtlasRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct TextureAtlas {
+    image: RgbaImage,
+    regions: BTreeMap<String, AtlasRegion>,
+    faces: HashMap<(BlockSize, Face), UvRect>,
+}
+
+impl TextureAtlas {
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    pub fn region(&self, texture: &str) -> Option<AtlasRegion> {
+        self.regions.get(texture).cloned()
+    }
+
+    pub fn texture_uv(&self, texture: &str) -> Option<UvRect> {
+        let region = self.regions.get(texture)?;
+        let (width, height) = (self.image.width as f32, self.image.height as f32);
+
+        Some(UvRect {
+            min: [region.x as f32 / width, region.y as f32 / height],
+            max: [(region.x + region.width) as f32 / width, (region.y + region.height) as f32 / height],
+        })
+    }
+
+    /// Texture coordinates of a face of `block`, every state of a block shares its textures.
+    pub fn uv(&self, block: Block, face: Face) -> Option<UvRect> {
+        self.faces.get(&(block.id(), face)).cloned()
+    }
+
+    /// Every textured block face, ordered by block id and then `Face::index`.
+    pub fn uv_table(&self) -> Vec<(BlockSize, Face, UvRect)> {
+        let mut table: Vec<_> = self.faces.iter().map(|((id, face), uv)| (*id, *face, *uv)).collect();
+        table.sort_by_key(|(id, face, _)| (*id, face.index()));
+        table
+    }
+}
+
+pub struct AtlasBuilder {
+    root: PathBuf,
+    padding: u32,
+    max_size: u32,
+    images: BTreeMap<String, RgbaImage>,
+}
+
+impl AtlasBuilder {
+    /// Builder loading textures relative to `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> AtlasBuilder {
+        AtlasBuilder {
+            root: root.as_ref().to_owned(),
+            padding: 0,
+            max_size: DEFAULT_MAX_ATLAS_SIZE,
+            images: BTreeMap::new(),
+        }
+    }
+
+    /// Pixels of each texture's edge to repeat around it.
+    pub fn padding(&mut self, padding: u32) -> &mut AtlasBuilder {
+        self.padding = padding;
+        self
+    }
+
+    pub fn max_size(&mut self, max_size: u32) -> &mut AtlasBuilder {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Use `image` for the texture `name` instead of loading it.
+    pub fn add_image(&mut self, name: &str, image: RgbaImage) -> &mut AtlasBuilder {
+        self.images.insert(name.to_owned(), image);
+        self
+    }
+
+    pub fn build(&self, registry: &BlockRegistry) -> Result<TextureAtlas, TextureError> {
+        let names: BTreeSet<&str> = registry
+            .declarations()
+            .flat_map(|(_, declaration)| Face::ALL.iter().filter_map(move |face| declaration.texture(*face)))
+            .collect();
+
+        let mut loaded = BTreeMap::new();
+        for name in names {
+            let image = match self.images.get(name) {
+                Some(image) => image.clone(),
+                None => RgbaImage::open(self.root.join(name))?,
+            };
+            loaded.insert(name.to_owned(), image);
+        }
+
+        let (size, placements) = self.pack(&loaded)?;
+        let mut image = RgbaImage::new(size, size);
+        let mut regions = BTreeMap::new();
+        for (name, (x, y)) in placements {
+            let texture = &loaded[&name];
+            image.blit(texture, x, y, self.padding);
+            regions.insert(name, AtlasRegion {
+                x: x + self.padding,
+                y: y + self.padding,
+                width: texture.width,
+                height: texture.height,
+            });
+        }
+
+        let mut atlas = TextureAtlas {
+            image,
+            regions,
+            faces: HashMap::new(),
+        };
+
+        for (block, declaration) in registry.declarations() {
+            for face in Face::ALL.iter() {
+                if let Some(uv) = declaration.texture(*face).and_then(|texture| atlas.texture_uv(texture)) {
+                    atlas.faces.insert((block.id(), *face), uv);
+                }
+            }
+        }
+
+        info!(util::LOG, "built {0}x{0} texture atlas of {1} textures", size, atlas.regions.len());
+        Ok(atlas)
+    }
+
+    /// Smallest square power of two atlas the textures fit in along with where each one goes.
+    ///
+    /// Textures are placed in rows from the tallest to the shortest, which wastes little space
+    /// for block textures that mostly share the same size.
+    fn pack(&self, images: &BTreeMap<String, RgbaImage>) -> Result<(u32, Placements), TextureError> {
+        let padded = |image: &RgbaImage| (image.width + 2 * self.padding, image.height + 2 * self.padding);
+
+        let mut order: Vec<(&String, (u32, u32))> = images.iter().map(|(name, image)| (name, padded(image))).collect();
+        order.sort_by(|(a_name, (a_width, a_height)), (b_name, (b_width, b_height))| {
+            b_height.cmp(a_height).then(b_width.cmp(a_width)).then(a_name.cmp(b_name))
+        });
+
+        let area: u64 = order.iter().map(|(_, (width, height))| *width as u64 * *height as u64).sum();
+        let widest = order.iter().map(|(_, (width, _))| *width).max().unwrap_or(1);
+        let mut size = widest.max((area as f64).sqrt().ceil() as u32).max(1).next_power_of_two();
+
+        while size <= self.max_size {
+            if let Some(placements) = shelf_pack(&order, size) {
+                let placements = placements
+                    .into_iter()
+                    .zip(order.iter())
+                    .map(|(position, (name, _))| ((*name).clone(), position))
+                    .collect();
+                return Ok((size, placements));
+            }
+            size *= 2;
+        }
+
+        Err(TextureError::AtlasFull { max_size: self.max_size })
+    }
+}
+
+fn shelf_pack(sizes: &[(&String, (u32, u32))], size: u32) -> Option<Vec<(u32, u32)>> {
+    let mut placements = Vec::with_capacity(sizes.len());
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+
+    for (_, (width, height)) in sizes {
+        if *width > size {
+            return None;
+        }
+
+        if x + width > size {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+
+        if y + height > size {
+            return None;
+        }
+
+        placements.push((x, y));
+        x += width;
+        shelf_height = shelf_height.max(*height);
+    }
+
+    Some(placements)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::{Block, BlockRegistry};
+    use crate::mesh::Face;
+    use crate::texture::{AtlasBuilder, AtlasRegion, RgbaImage, TextureError, UvRect};
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    const REGISTRY: &str = r#"{
+        "0": { "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0, "textures": { "all": "red.png" } },
+        "2": {
+            "group": "Dirt",
+            "name": "Grass",
+            "color": [60, 160, 60],
+            "transparency": 0,
+            "textures": { "all": "red.png", "top": "green.png", "side": "blue.png" }
+        }
+    }"#;
+
+    fn builder() -> AtlasBuilder {
+        let mut builder = AtlasBuilder::new("does/not/exist");
+        builder
+            .add_image("red.png", RgbaImage::filled(2, 2, RED))
+            .add_image("green.png", RgbaImage::filled(2, 2, GREEN))
+            .add_image("blue.png", RgbaImage::filled(4, 2, BLUE))
+            .add_image("unused.png", RgbaImage::filled(64, 64, CLEAR));
+        builder
+    }
+
+    #[test]
+    fn atlas_layout() {
+        let (registry, _) = BlockRegistry::from_str(REGISTRY).unwrap();
+        let atlas = builder().build(&registry).unwrap();
+
+        // The wide texture takes the first row, the others share the second.
+        let expected = [
+            [BLUE, BLUE, BLUE, BLUE],
+            [BLUE, BLUE, BLUE, BLUE],
+            [GREEN, GREEN, RED, RED],
+            [GREEN, GREEN, RED, RED],
+        ];
+        let image = atlas.image();
+        assert_eq!((image.width(), image.height()), (4, 4));
+        for (y, row) in expected.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                assert_eq!(image.pixel(x as u32, y as u32), *pixel, "pixel ({}, {})", x, y);
+            }
+        }
+
+        assert_eq!(atlas.region("red.png"), Some(AtlasRegion { x: 2, y: 2, width: 2, height: 2 }));
+        assert!(atlas.region("unused.png").is_none());
+
+        let red = UvRect { min: [0.5, 0.5], max: [1.0, 1.0] };
+        let green = UvRect { min: [0.0, 0.5], max: [0.5, 1.0] };
+        let blue = UvRect { min: [0.0, 0.0], max: [1.0, 0.5] };
+
+        let table = atlas.uv_table();
+        assert_eq!(table.len(), 12);
+        assert!(table[..6].iter().all(|(id, _, uv)| *id == 1 && *uv == red));
+        let grass: Vec<_> = table[6..].iter().map(|(_, face, uv)| (*face, *uv)).collect();
+        assert_eq!(grass, vec![
+            (Face::PosX, blue),
+            (Face::PosY, green),
+            (Face::PosZ, blue),
+            (Face::NegX, blue),
+            (Face::NegY, red),
+            (Face::NegZ, blue),
+        ]);
+
+        // States share the textures of their block, air has none.
+        assert_eq!(atlas.uv(Block::with_state(2, 3), Face::PosY), Some(green));
+        assert_eq!(atlas.uv(Block::hard_create(0), Face::PosY), None);
+    }
+
+    #[test]
+    fn atlas_padding() {
+        let (registry, _) = BlockRegistry::from_str(REGISTRY).unwrap();
+        let atlas = builder().padding(1).build(&registry).unwrap();
+
+        let image = atlas.image();
+        assert_eq!((image.width(), image.height()), (8, 8));
+        assert_eq!(atlas.region("green.png"), Some(AtlasRegion { x: 1, y: 5, width: 2, height: 2 }));
+        assert_eq!(atlas.texture_uv("green.png"), Some(UvRect { min: [0.125, 0.625], max: [0.375, 0.875] }));
+
+        // Edges are repeated into the padding.
+        assert_eq!(image.pixel(0, 4), GREEN);
+        assert_eq!(image.pixel(3, 7), GREEN);
+        assert_eq!(image.pixel(4, 4), RED);
+        assert_eq!(image.pixel(5, 0), BLUE);
+        assert_eq!(image.pixel(7, 7), RED);
+    }
+
+    #[test]
+    fn atlas_files() {
+        let root = std::env::temp_dir().join(format!("voxel-atlas-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut gradient = RgbaImage::new(3, 2);
+        gradient.set_pixel(2, 1, [10, 20, 30, 40]);
+        gradient.save(root.join("red.png")).unwrap();
+        assert_eq!(RgbaImage::open(root.join("red.png")).unwrap(), gradient);
+
+        let (registry, _) = BlockRegistry::from_str(r#"{
+            "1": { "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0, "textures": { "all": "red.png" } }
+        }"#).unwrap();
+        let atlas = AtlasBuilder::new(&root).build(&registry).unwrap();
+        assert_eq!(atlas.image().pixel(2, 1), [10, 20, 30, 40]);
+
+        // Referencing a texture that doesn't exist names the file.
+        let (registry, _) = BlockRegistry::from_str(REGISTRY).unwrap();
+        match AtlasBuilder::new(&root).build(&registry) {
+            Err(TextureError::IO { path, .. }) => assert!(path.ends_with("blue.png")),
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("missing texture was loaded"),
+        }
+
+        match builder().max_size(2).build(&registry) {
+            Err(TextureError::AtlasFull { max_size: 2 }) => {},
+            _ => panic!("atlas should be full"),
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,724 @@
+//! Importing and exporting MagicaVoxel `.vox` models.
+//!
+//! Only the model data is read: `SIZE` and `XYZI` chunks for each model and the `RGBA` palette.
+//! Scene graph, material and layer chunks are skipped, and files without a palette use
+//! MagicaVoxel's default one.
+//!
+//! MagicaVoxel's z axis points up while ours is y, so a voxel at `(x, y, z)` of a model with size
+//! `(sx, sy, sz)` ends up at `(x, z, sy - 1 - y)`. Swapping the axes without flipping one of them
+//! would mirror every model.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, BlockSize, BlockRegistry, BlockRegistryFile, BlockDeclaration, BlockIdentifier};
+use crate::block::{MAX_BLOCK_ID, EMPTY_BLOCK};
+use crate::chunk::{Chunk, ChunkMut, BoxedChunk, ChunkPosition, LocalBlockPosition};
+use crate::chunk::{CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_LENGTH};
+
+pub const VOX_VERSION: i32 = 150;
+/// Largest model MagicaVoxel can open along each axis.
+pub const MAX_MODEL_SIZE: usize = 256;
+
+/// Group of the blocks declared for colors that had no matching block.
+pub const VOX_GROUP: &str = "Vox";
+pub const VOX_NAMESPACE: &str = "vox";
+
+#[derive(Debug)]
+pub enum VoxError {
+    IO {
+        path: Option<PathBuf>,
+        error: io::Error,
+    },
+    InvalidMagic,
+    Malformed(String),
+    /// A model larger than `MAX_MODEL_SIZE` along some axis.
+    TooLarge {
+        size: (usize, usize, usize),
+    },
+    /// More distinct block colors than the 255 a palette holds.
+    TooManyColors,
+    /// Nothing in the registry to match colors to and declaring new blocks is disabled.
+    NoBlocks,
+    /// Every block id is taken, so no more blocks can be declared.
+    NoFreeIds,
+}
+
+impl VoxError {
+    fn malformed<S: Into<String>>(reason: S) -> VoxError {
+        VoxError::Malformed(reason.into())
+    }
+
+    fn at(self, file: &Path) -> VoxError {
+        match self {
+            VoxError::IO { error, .. } => VoxError::IO {
+                path: Some(file.to_owned()),
+                error,
+            },
+            error => error,
+        }
+    }
+}
+
+impl From<io::Error> for VoxError {
+    fn from(error: io::Error) -> VoxError {
+        VoxError::IO { path: None, error }
+    }
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxError::IO { path: Some(path), error } => write!(f, "{}: {}", path.display(), error),
+            VoxError::IO { path: None, error } => write!(f, "{}", error),
+            VoxError::InvalidMagic => write!(f, "not a .vox file"),
+            VoxError::Malformed(reason) => write!(f, "invalid .vox file: {}", reason),
+            VoxError::TooLarge { size: (x, y, z) } => {
+                write!(f, "model of size {}x{}x{} is larger than {}", x, y, z, MAX_MODEL_SIZE)
+            },
+            VoxError::TooManyColors => write!(f, "more than 255 block colors"),
+            VoxError::NoBlocks => write!(f, "no visible blocks to match colors to"),
+            VoxError::NoFreeIds => write!(f, "no free block ids left"),
+        }
+    }
+}
+
+impl Error for VoxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            VoxError::IO { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The 255 RGBA colors voxels refer to by index, index 0 means no voxel.
+#[derive(Clone, Eq, PartialEq)]
+pub struct VoxPalette {
+    colors: [[u8; 4]; 256],
+}
+
+impl VoxPalette {
+    pub fn color(&self, index: u8) -> [u8; 4] {
+        self.colors[index as usize]
+    }
+
+    pub fn set_color(&mut self, index: u8, color: [u8; 4]) {
+        assert!(index != 0, "palette index 0 is reserved for empty voxels");
+        self.colors[index as usize] = color;
+    }
+}
+
+/// MagicaVoxel's default palette.
+///
+/// Indices 1 to 215 are a 6×6×6 color cube without black, stepping from 0xff down to 0x00 with blue
+/// changing fastest. The remaining 40 are ramps of 10 shades of blue, green, red and gray.
+impl Default for VoxPalette {
+    fn default() -> VoxPalette {
+        const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+        const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+        let mut colors = vec![[0u8; 4]];
+        for r in CUBE.iter() {
+            for g in CUBE.iter() {
+                for b in CUBE.iter() {
+                    colors.push([*r, *g, *b, 0xff]);
+                }
+            }
+        }
+        // Black isn't part of the cube.
+        colors.pop();
+
+        for channel in &[2, 1, 0] {
+            for shade in RAMP.iter() {
+                let mut color = [0, 0, 0, 0xff];
+                color[*channel] = *shade;
+                colors.push(color);
+            }
+        }
+        colors.extend(RAMP.iter().map(|shade| [*shade, *shade, *shade, 0xff]));
+
+        let mut palette = VoxPalette { colors: [[0; 4]; 256] };
+        palette.colors.copy_from_slice(&colors);
+        palette
+    }
+}
+
+impl fmt::Debug for VoxPalette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.colors[1..].iter()).finish()
+    }
+}
+
+/// A voxel of a model, in our axes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Voxel {
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+    /// Palette index of the voxel's color, from 1 to 255.
+    pub color: u8,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VoxModel {
+    size: (usize, usize, usize),
+    voxels: Vec<Voxel>,
+}
+
+impl VoxModel {
+    /// Empty model of the given size in our axes.
+    pub fn new(size: (usize, usize, usize)) -> Result<VoxModel, VoxError> {
+        let (x, y, z) = size;
+        if x == 0 || y == 0 || z == 0 || x > MAX_MODEL_SIZE || y > MAX_MODEL_SIZE || z > MAX_MODEL_SIZE {
+            return Err(VoxError::TooLarge { size });
+        }
+
+        Ok(VoxModel {
+            size,
+            voxels: Vec::new(),
+        })
+    }
+
+    pub fn size(&self) -> (usize, usize, usize) {
+        self.size
+    }
+
+    pub fn voxels(&self) -> &[Voxel] {
+        &self.voxels
+    }
+
+    /// Add a voxel, which has to be inside the model and can't have color 0.
+    pub fn push(&mut self, voxel: Voxel) {
+        let (x, y, z) = self.size;
+        assert!(voxel.x < x && voxel.y < y && voxel.z < z, "{:?} is outside of the model", voxel);
+        assert!(voxel.color != 0, "voxel color 0 is reserved for empty voxels");
+        self.voxels.push(voxel);
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    pub palette: VoxPalette,
+}
+
+impl VoxFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<VoxFile, VoxError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| VoxError::from(e).at(path))?;
+        VoxFile::from_bytes(&bytes)
+    }
+
+    pub fn from_reader<R: io::Read>(mut reader: R) -> Result<VoxFile, VoxError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        VoxFile::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<VoxFile, VoxError> {
+        if bytes.len() < 8 || &bytes[0..4] != b"VOX " {
+            return Err(VoxError::InvalidMagic);
+        }
+
+        let mut rest = &bytes[8..];
+        let main = read_chunk(&mut rest)?;
+        if main.id != *b"MAIN" {
+            return Err(VoxError::malformed("first chunk isn't MAIN"));
+        }
+
+        let mut file = VoxFile::default();
+        let mut size = None;
+        let mut children = main.children;
+        while !children.is_empty() {
+            let chunk = read_chunk(&mut children)?;
+            let mut content = chunk.content;
+            match &chunk.id {
+                b"SIZE" => {
+                    let x = read_size(&mut content)?;
+                    let y = read_size(&mut content)?;
+                    let z = read_size(&mut content)?;
+                    size = Some((x, y, z));
+                },
+                b"XYZI" => {
+                    let (sx, sy, sz) = size.take().ok_or_else(|| VoxError::malformed("XYZI without SIZE"))?;
+                    let mut model = VoxModel::new((sx, sz, sy))?;
+
+                    let count = read_i32(&mut content)?;
+                    let length = usize::try_from(count)
+                        .ok()
+                        .and_then(|count| count.checked_mul(4))
+                        .ok_or_else(|| VoxError::malformed(format!("invalid voxel count {}", count)))?;
+                    if content.len() < length {
+                        return Err(VoxError::malformed("XYZI has fewer voxels than it claims"));
+                    }
+                    for voxel in content[..length].chunks(4) {
+                        let (x, y, z, color) = (voxel[0] as usize, voxel[1] as usize, voxel[2] as usize, voxel[3]);
+                        if x >= sx || y >= sy || z >= sz {
+                            return Err(VoxError::malformed(format!("voxel ({}, {}, {}) is outside of its model", x, y, z)));
+                        }
+                        if color != 0 {
+                            model.push(Voxel {
+                                x,
+                                y: z,
+                                z: sy - 1 - y,
+                                color,
+                            });
+                        }
+                    }
+                    file.models.push(model);
+                },
+                b"RGBA" => {
+                    if content.len() < 256 * 4 {
+                        return Err(VoxError::malformed("RGBA has fewer than 256 colors"));
+                    }
+                    // The file's first color is index 1, the last one is unused.
+                    for (index, color) in content.chunks(4).take(255).enumerate() {
+                        file.palette.colors[index + 1].copy_from_slice(color);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        Ok(file)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), VoxError> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes()).map_err(|e| VoxError::from(e).at(path))
+    }
+
+    pub fn write<W: io::Write>(&self, mut writer: W) -> Result<(), VoxError> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+        for model in &self.models {
+            let (x, y, z) = model.size;
+            let mut size = Vec::new();
+            for dimension in &[x, z, y] {
+                size.extend_from_slice(&(*dimension as i32).to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &size, &[]);
+
+            let mut voxels = Vec::with_capacity(4 + model.voxels.len() * 4);
+            voxels.extend_from_slice(&(model.voxels.len() as i32).to_le_bytes());
+            for voxel in &model.voxels {
+                voxels.extend_from_slice(&[voxel.x as u8, (z - 1 - voxel.z) as u8, voxel.y as u8, voxel.color]);
+            }
+            write_chunk(&mut children, b"XYZI", &voxels, &[]);
+        }
+
+        let mut colors = Vec::with_capacity(256 * 4);
+        for color in self.palette.colors[1..].iter() {
+            colors.extend_from_slice(color);
+        }
+        colors.extend_from_slice(&[0; 4]);
+        write_chunk(&mut children, b"RGBA", &colors, &[]);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"VOX ");
+        bytes.extend_from_slice(&VOX_VERSION.to_le_bytes());
+        write_chunk(&mut bytes, b"MAIN", &[], &children);
+        bytes
+    }
+}
+
+struct RawChunk<'a> {
+    id: [u8; 4],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+fn read_chunk<'a>(bytes: &mut &'a [u8]) -> Result<RawChunk<'a>, VoxError> {
+    if bytes.len() < 12 {
+        return Err(VoxError::malformed("truncated chunk header"));
+    }
+
+    let mut id = [0; 4];
+    id.copy_from_slice(&bytes[0..4]);
+    let mut header = &bytes[4..12];
+    let content_len = read_i32(&mut header)? as usize;
+    let children_len = read_i32(&mut header)? as usize;
+
+    let rest = &bytes[12..];
+    if rest.len() < content_len.saturating_add(children_len) {
+        return Err(VoxError::malformed(format!("truncated {} chunk", String::from_utf8_lossy(&id))));
+    }
+
+    let (content, rest) = rest.split_at(content_len);
+    let (children, rest) = rest.split_at(children_len);
+    *bytes = rest;
+    Ok(RawChunk { id, content, children })
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    out.extend_from_slice(content);
+    out.extend_from_slice(children);
+}
+
+fn read_i32(bytes: &mut &[u8]) -> Result<i32, VoxError> {
+    if bytes.len() < 4 {
+        return Err(VoxError::malformed("unexpected end of chunk"));
+    }
+
+    let value = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    *bytes = &bytes[4..];
+    Ok(value)
+}
+
+fn read_size(bytes: &mut &[u8]) -> Result<usize, VoxError> {
+    let size = read_i32(bytes)?;
+    if size <= 0 || size as usize > MAX_MODEL_SIZE {
+        return Err(VoxError::malformed(format!("invalid model size {}", size)));
+    }
+    Ok(size as usize)
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let square = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    square(r1, r2) + square(g1, g2) + square(b1, b2)
+}
+
+/// Models of a `.vox` file turned into chunks.
+#[derive(Debug)]
+pub struct VoxImport {
+    /// Chunks of each model, with the model's lowest corner at the origin of chunk (0, 0, 0).
+    pub models: Vec<BTreeMap<ChunkPosition, BoxedChunk>>,
+    /// Blocks declared for colors without a close enough block, which the chunks already use and
+    /// have to be merged into the registry.
+    pub declarations: BlockRegistryFile,
+}
+
+/// Turns `.vox` models into chunks, matching each palette color to the block with the closest
+/// `BlockDeclaration::color`.
+pub struct VoxImporter<'a> {
+    registry: &'a BlockRegistry,
+    max_distance: Option<u32>,
+}
+
+impl<'a> VoxImporter<'a> {
+    pub fn new(registry: &'a BlockRegistry) -> VoxImporter<'a> {
+        VoxImporter {
+            registry,
+            max_distance: None,
+        }
+    }
+
+    /// Declare a new block for colors whose closest block is further away than `distance`, measured
+    /// as the euclidean distance between RGB colors. By default the closest block is always used.
+    pub fn declare_beyond(&mut self, distance: u32) -> &mut VoxImporter<'a> {
+        self.max_distance = Some(distance);
+        self
+    }
+
+    pub fn import(&self, file: &VoxFile) -> Result<VoxImport, VoxError> {
+        let mut candidates: Vec<((u8, u8, u8), Block)> = self
+            .registry
+            .declarations()
+            .filter(|(_, declaration)| declaration.visible())
+            .map(|(block, declaration)| (declaration.color(), block))
+            .collect();
+        // New blocks fill the gaps between declared ids, never taking the id of the empty block.
+        let declared: HashSet<usize> = self.registry.declarations().map(|(block, _)| block.id() as usize).collect();
+        let mut free_ids = (0..=MAX_BLOCK_ID).filter(|id| *id != EMPTY_BLOCK.id() as usize && !declared.contains(id));
+
+        let mut declarations = BlockRegistryFile::default();
+        let mut blocks: HashMap<u8, Block> = HashMap::new();
+        let mut models = Vec::with_capacity(file.models.len());
+        for model in &file.models {
+            let mut chunks = BTreeMap::new();
+            for voxel in &model.voxels {
+                let block = match blocks.get(&voxel.color) {
+                    Some(block) => *block,
+                    None => {
+                        let [r, g, b, _] = file.palette.color(voxel.color);
+                        let color = (r, g, b);
+                        let nearest = candidates
+                            .iter()
+                            .map(|(candidate, block)| (distance(color, *candidate), *block))
+                            .min_by_key(|(distance, _)| *distance);
+
+                        let block = match (nearest, self.max_distance) {
+                            (Some((distance, block)), Some(max)) if distance <= max.saturating_mul(max) => block,
+                            (Some((_, block)), None) => block,
+                            (None, None) => return Err(VoxError::NoBlocks),
+                            (_, Some(_)) => {
+                                let id = free_ids.next().ok_or(VoxError::NoFreeIds)?;
+                                let block = Block::hard_create(id as BlockSize);
+                                declarations.insert(id, declare(color));
+                                candidates.push((color, block));
+                                block
+                            },
+                        };
+                        blocks.insert(voxel.color, block);
+                        block
+                    },
+                };
+
+                let position = ChunkPosition::new(
+                    (voxel.x / CHUNK_WIDTH) as i32,
+                    (voxel.y / CHUNK_HEIGHT) as i32,
+                    (voxel.z / CHUNK_LENGTH) as i32,
+                );
+                let local = LocalBlockPosition::unchecked_new(
+                    voxel.x % CHUNK_WIDTH,
+                    voxel.y % CHUNK_HEIGHT,
+                    voxel.z % CHUNK_LENGTH,
+                );
+                chunks.entry(position).or_insert_with(BoxedChunk::empty).set_block(&local, block);
+            }
+            models.push(chunks);
+        }
+
+        if !declarations.is_empty() {
+            info!(util::LOG, "declared {} blocks for .vox colors", declarations.len());
+        }
+
+        Ok(VoxImport { models, declarations })
+    }
+}
+
+fn declare((r, g, b): (u8, u8, u8)) -> BlockDeclaration {
+    let identifier = BlockIdentifier::new(VOX_NAMESPACE, &format!("color_{:02x}{:02x}{:02x}", r, g, b))
+        .expect("hex colors are valid identifiers");
+    let name = format!("Vox {:02X}{:02X}{:02X}", r, g, b);
+    BlockDeclaration::new(VOX_GROUP, &name, (r, g, b), 0).with_identifier(&identifier)
+}
+
+/// Export the blocks of `chunk` from `min` to `max` inclusive as a single model.
+///
+/// Each distinct color of the visible blocks gets a palette entry, blocks that are invisible or not
+/// declared at all are left empty.
+pub fn export_region<C: Chunk + ?Sized>(
+    chunk: &C,
+    min: LocalBlockPosition,
+    max: LocalBlockPosition,
+    registry: &BlockRegistry,
+) -> Result<VoxFile, VoxError> {
+    assert!(min.x() <= max.x() && min.y() <= max.y() && min.z() <= max.z(), "{:?} is above {:?}", min, max);
+    let size = (max.x() + 1 - min.x(), max.y() + 1 - min.y(), max.z() + 1 - min.z());
+    let mut model = VoxModel::new(size)?;
+    let mut palette = VoxPalette::default();
+    let mut colors: HashMap<(u8, u8, u8), u8> = HashMap::new();
+
+    for y in min.y()..=max.y() {
+        for x in min.x()..=max.x() {
+            for z in min.z()..=max.z() {
+                let block = chunk.block(&LocalBlockPosition::unchecked_new(x, y, z));
+                let color = match registry.declaration(block) {
+                    Some(declaration) if declaration.visible() => declaration.color(),
+                    _ => continue,
+                };
+
+                let index = match colors.get(&color) {
+                    Some(index) => *index,
+                    None => {
+                        if colors.len() == 255 {
+                            return Err(VoxError::TooManyColors);
+                        }
+
+                        let index = colors.len() as u8 + 1;
+                        palette.set_color(index, [color.0, color.1, color.2, 0xff]);
+                        colors.insert(color, index);
+                        index
+                    },
+                };
+
+                model.push(Voxel {
+                    x: x - min.x(),
+                    y: y - min.y(),
+                    z: z - min.z(),
+                    color: index,
+                });
+            }
+        }
+    }
+
+    Ok(VoxFile {
+        models: vec![model],
+        palette,
+    })
+}
+
+/// Export every block of `chunk`, see `export_region`.
+pub fn export_chunk<C: Chunk + ?Sized>(chunk: &C, registry: &BlockRegistry) -> Result<VoxFile, VoxError> {
+    let max = LocalBlockPosition::unchecked_new(CHUNK_WIDTH - 1, CHUNK_HEIGHT - 1, CHUNK_LENGTH - 1);
+    export_region(chunk, LocalBlockPosition::unchecked_new(0, 0, 0), max, registry)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::block::{Block, BlockSize, BlockRegistry, BlockDeclaration, OverridePolicy, EMPTY_BLOCK, MAX_BLOCK_ID};
+    use crate::chunk::{Chunk, ChunkMut, BoxedChunk, ChunkPosition, LocalBlockPosition};
+    use crate::vox::{VoxFile, VoxImporter, VoxModel, VoxError, VoxPalette, Voxel, export_region, export_chunk};
+
+    const REGISTRY: &str = r#"{
+        "0": { "identifier": "core:air", "group": "Air", "name": "Air", "color": [0, 0, 0], "transparency": 255 },
+        "1": { "identifier": "core:dirt", "group": "Dirt", "name": "Dirt", "color": [165, 42, 42], "transparency": 0 },
+        "2": { "identifier": "core:stone", "group": "Stone", "name": "Stone", "color": [128, 128, 128], "transparency": 0 }
+    }"#;
+
+    fn chunk(id: [u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes.extend_from_slice(children);
+        bytes
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn default_palette() {
+        let palette = VoxPalette::default();
+        assert_eq!(palette.color(1), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(palette.color(2), [0xff, 0xff, 0xcc, 0xff]);
+        assert_eq!(palette.color(7), [0xff, 0xcc, 0xff, 0xff]);
+        assert_eq!(palette.color(215), [0x00, 0x00, 0x33, 0xff]);
+        assert_eq!(palette.color(216), [0x00, 0x00, 0xee, 0xff]);
+        assert_eq!(palette.color(226), [0x00, 0xee, 0x00, 0xff]);
+        assert_eq!(palette.color(236), [0xee, 0x00, 0x00, 0xff]);
+        assert_eq!(palette.color(246), [0xee, 0xee, 0xee, 0xff]);
+        assert_eq!(palette.color(255), [0x11, 0x11, 0x11, 0xff]);
+    }
+
+    #[test]
+    fn read_write() {
+        // A model of size (4, 5, 6) in MagicaVoxel's axes with a scene graph chunk to skip.
+        let mut children = chunk(*b"SIZE", &ints(&[4, 5, 6]), &[]);
+        let mut voxels = ints(&[2]);
+        voxels.extend_from_slice(&[1, 2, 3, 9, 0, 0, 0, 216]);
+        children.extend(chunk(*b"XYZI", &voxels, &[]));
+        children.extend(chunk(*b"nTRN", &[1, 2, 3, 4], &[]));
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(ints(&[150]));
+        bytes.extend(chunk(*b"MAIN", &[], &children));
+
+        let file = VoxFile::from_bytes(&bytes).unwrap();
+        assert_eq!(file.palette, VoxPalette::default());
+        assert_eq!(file.models.len(), 1);
+        let model = &file.models[0];
+        assert_eq!(model.size(), (4, 6, 5));
+        assert_eq!(model.voxels()[0], Voxel { x: 1, y: 3, z: 2, color: 9 });
+        assert_eq!(model.voxels()[1], Voxel { x: 0, y: 0, z: 4, color: 216 });
+
+        let mut written = file.clone();
+        let mut second = VoxModel::new((256, 1, 2)).unwrap();
+        second.push(Voxel { x: 255, y: 0, z: 1, color: 3 });
+        written.models.push(second);
+        written.palette.set_color(3, [1, 2, 3, 4]);
+        assert_eq!(VoxFile::from_bytes(&written.to_bytes()).unwrap(), written);
+
+        assert!(matches!(VoxFile::from_bytes(b"VOXEL"), Err(VoxError::InvalidMagic)));
+        assert!(matches!(VoxFile::from_bytes(&bytes[..bytes.len() - 1]), Err(VoxError::Malformed(_))));
+        assert!(matches!(VoxModel::new((257, 1, 1)), Err(VoxError::TooLarge { .. })));
+
+        // Voxel counts that are negative or larger than the chunk.
+        for count in &[-1, i32::MIN, i32::MAX, 3] {
+            let mut children = chunk(*b"SIZE", &ints(&[4, 5, 6]), &[]);
+            let mut voxels = ints(&[*count]);
+            voxels.extend_from_slice(&[1, 2, 3, 9, 0, 0, 0, 216]);
+            children.extend(chunk(*b"XYZI", &voxels, &[]));
+            let mut bytes = b"VOX ".to_vec();
+            bytes.extend(ints(&[150]));
+            bytes.extend(chunk(*b"MAIN", &[], &children));
+            assert!(matches!(VoxFile::from_bytes(&bytes), Err(VoxError::Malformed(_))), "count {}", count);
+        }
+    }
+
+    #[test]
+    fn import() {
+        let (mut registry, _) = BlockRegistry::from_str(REGISTRY).unwrap();
+        let mut file = VoxFile::default();
+        file.palette.set_color(1, [160, 40, 40, 255]);
+        file.palette.set_color(2, [0, 0, 250, 255]);
+        let mut model = VoxModel::new((100, 3, 2)).unwrap();
+        model.push(Voxel { x: 0, y: 0, z: 0, color: 1 });
+        model.push(Voxel { x: 70, y: 2, z: 1, color: 2 });
+        file.models.push(model);
+
+        // Without declaring blocks, blue is closest to stone.
+        let import = VoxImporter::new(&registry).import(&file).unwrap();
+        assert!(import.declarations.is_empty());
+        let chunks = &import.models[0];
+        assert_eq!(chunks.len(), 2);
+        let origin = &chunks[&ChunkPosition::new(0, 0, 0)];
+        assert_eq!(origin.block(&LocalBlockPosition::unchecked_new(0, 0, 0)), Block::hard_create(1));
+        assert_eq!(origin.block(&LocalBlockPosition::unchecked_new(1, 0, 0)), EMPTY_BLOCK);
+        let far = &chunks[&ChunkPosition::new(1, 0, 0)];
+        assert_eq!(far.block(&LocalBlockPosition::unchecked_new(6, 2, 1)), Block::hard_create(2));
+
+        let import = VoxImporter::new(&registry).declare_beyond(20).import(&file).unwrap();
+        assert_eq!(import.declarations.len(), 1);
+        let declared = import.declarations.declaration(3).unwrap();
+        assert_eq!(declared.color(), (0, 0, 250));
+        assert!(import.declarations.merge_into(&mut registry, None, OverridePolicy::Reject).is_empty());
+        assert_eq!(registry.block_by_name("vox:color_0000fa"), Some(Block::hard_create(3)));
+        let far = &import.models[0][&ChunkPosition::new(1, 0, 0)];
+        assert_eq!(far.block(&LocalBlockPosition::unchecked_new(6, 2, 1)), Block::hard_create(3));
+
+        let empty = BlockRegistry::empty();
+        assert!(matches!(VoxImporter::new(&empty).import(&file), Err(VoxError::NoBlocks)));
+
+        // With the last id taken, new blocks go into the gaps below it.
+        let (mut registry, _) = BlockRegistry::from_str(REGISTRY).unwrap();
+        let last = BlockDeclaration::new("Last", "Last", (255, 255, 255), 0);
+        registry.set_declaration(MAX_BLOCK_ID as BlockSize, Some(last)).unwrap();
+        file.palette.set_color(3, [0, 250, 0, 255]);
+        file.models[0].push(Voxel { x: 1, y: 0, z: 0, color: 3 });
+        let import = VoxImporter::new(&registry).declare_beyond(20).import(&file).unwrap();
+        assert_eq!(import.declarations.len(), 2);
+        assert_eq!(import.declarations.declaration(3).unwrap().color(), (0, 0, 250));
+        assert_eq!(import.declarations.declaration(4).unwrap().color(), (0, 250, 0));
+    }
+
+    #[test]
+    fn export() {
+        let (registry, _) = BlockRegistry::from_str(REGISTRY).unwrap();
+        let mut chunk = BoxedChunk::flat(Block::hard_create(2), 0);
+        chunk.set_block(&LocalBlockPosition::unchecked_new(3, 4, 5), Block::hard_create(1));
+        // Not declared, so left out.
+        chunk.set_block(&LocalBlockPosition::unchecked_new(3, 5, 5), Block::hard_create(9));
+
+        let file = export_chunk(&chunk, &registry).unwrap();
+        assert_eq!(file.models[0].size(), (64, 64, 64));
+        assert_eq!(file.models[0].voxels().len(), 63 * 63 + 1);
+
+        let bytes = file.to_bytes();
+        let import = VoxImporter::new(&registry).import(&VoxFile::from_bytes(&bytes).unwrap()).unwrap();
+        let imported = &import.models[0][&ChunkPosition::new(0, 0, 0)];
+        for (x, y, z) in &[(0, 0, 0), (62, 0, 62), (63, 0, 63), (3, 4, 5), (3, 5, 5)] {
+            let position = LocalBlockPosition::unchecked_new(*x, *y, *z);
+            let expected = match chunk.block(&position) {
+                block if block.id() == 9 => EMPTY_BLOCK,
+                block => block,
+            };
+            assert_eq!(imported.block(&position), expected, "{:?}", position);
+        }
+
+        let region = export_region(
+            &chunk,
+            LocalBlockPosition::unchecked_new(2, 3, 4),
+            LocalBlockPosition::unchecked_new(4, 4, 6),
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(region.models[0].size(), (3, 2, 3));
+        assert_eq!(region.models[0].voxels(), &[Voxel { x: 1, y: 1, z: 1, color: 1 }]);
+        assert_eq!(region.palette.color(1), [165, 42, 42, 255]);
+    }
+}
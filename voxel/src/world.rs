@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::collections::hash_map;
+
+use crate::block::Block;
+use crate::chunk::{Chunk, ChunkMut, BoxedChunk, ChunkPosition, WorldBlockPosition, ChunkNeighborhood, MissingNeighbor};
+use crate::mesh::Face;
+
+/// Collection of chunks addressed by their `ChunkPosition`.
+pub struct World<C = BoxedChunk> {
+    chunks: HashMap<ChunkPosition, C>,
+}
+
+impl<C: Chunk + ChunkMut + Default> World<C> {
+    pub fn new() -> World<C> {
+        World {
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn chunk(&self, position: &ChunkPosition) -> Option<&C> {
+        self.chunks.get(position)
+    }
+
+    pub fn chunk_mut(&mut self, position: &ChunkPosition) -> Option<&mut C> {
+        self.chunks.get_mut(position)
+    }
+
+    pub fn insert_chunk(&mut self, position: ChunkPosition, chunk: C) -> Option<C> {
+        self.chunks.insert(position, chunk)
+    }
+
+    pub fn remove_chunk(&mut self, position: &ChunkPosition) -> Option<C> {
+        self.chunks.remove(position)
+    }
+
+    pub fn chunks(&self) -> hash_map::Iter<'_, ChunkPosition, C> {
+        self.chunks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// The chunk at `position` along with whichever of its neighbours are loaded.
+    ///
+    /// Neighbours past the edge of the world count as missing.
+    pub fn neighborhood(&self, position: &ChunkPosition, missing: MissingNeighbor) -> Option<ChunkNeighborhood<'_>> {
+        let center = self.chunks.get(position)?;
+        let mut neighborhood = ChunkNeighborhood::new(center, missing);
+        for face in Face::ALL.iter() {
+            let (x, y, z) = face.offset();
+            let neighbor = position.checked_offset(x as i32, y as i32, z as i32).and_then(|neighbor| self.chunks.get(&neighbor));
+            neighborhood.set_neighbor(*face, neighbor.map(|chunk| chunk as &dyn Chunk));
+        }
+        Some(neighborhood)
+    }
+
+    /// Block at a world position, `None` if the chunk containing it is not loaded or can't exist.
+    pub fn block(&self, position: &WorldBlockPosition) -> Option<Block> {
+        let chunk = position.checked_chunk()?;
+        self.chunks.get(&chunk).map(|chunk| chunk.block(&position.local()))
+    }
+
+    /// Set the block at a world position, creating an empty chunk if it is not loaded.
+    ///
+    /// Returns `false` without changing anything if the position is too far out for its chunk to
+    /// have a `ChunkPosition`.
+    pub fn set_block(&mut self, position: &WorldBlockPosition, block: Block) -> bool {
+        let chunk = match position.checked_chunk() {
+            Some(chunk) => chunk,
+            None => return false,
+        };
+        self.chunks
+            .entry(chunk)
+            .or_default()
+            .set_block(&position.local(), block);
+        true
+    }
+}
+
+impl<C: Chunk + ChunkMut + Default> Default for World<C> {
+    fn default() -> World<C> {
+        World::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::world::World;
+    use crate::chunk::{PalettedChunk, ChunkPosition, WorldBlockPosition, MissingNeighbor, CHUNK_WIDTH, CHUNK_HEIGHT};
+    use crate::block::{Block, BlockRegistry, EMPTY_BLOCK};
+
+    #[test]
+    fn world_blocks() {
+        let mut world: World = World::new();
+        let dirt = Block::hard_create(1);
+
+        let positions = [
+            WorldBlockPosition::new(0, 0, 0),
+            WorldBlockPosition::new(-1, -1, -1),
+            WorldBlockPosition::new(63, -64, 64),
+            WorldBlockPosition::new(-1000, 5, 1000),
+        ];
+
+        for position in positions.iter() {
+            assert_eq!(world.block(position), None);
+            assert!(world.set_block(position, dirt));
+            assert_eq!(world.block(position), Some(dirt));
+        }
+
+        assert_eq!(world.len(), 4);
+        assert!(world.chunk(&ChunkPosition::new(-1, -1, -1)).is_some());
+        assert_eq!(world.block(&WorldBlockPosition::new(-2, -1, -1)), Some(EMPTY_BLOCK));
+
+        // Blocks past the last chunk position are never there.
+        let far = WorldBlockPosition::new(i64::MIN, 0, 0);
+        assert!(!world.set_block(&far, dirt));
+        assert_eq!(world.block(&far), None);
+        assert_eq!(world.len(), 4);
+    }
+
+    #[test]
+    fn world_paletted() {
+        let mut world: World<PalettedChunk> = World::new();
+        let position = WorldBlockPosition::new(-70, 3, 9);
+        world.set_block(&position, Block::hard_create(1));
+
+        let chunk = world.remove_chunk(&ChunkPosition::new(-2, 0, 0)).unwrap();
+        assert_eq!(chunk.palette().len(), 2);
+        assert_eq!(world.block(&position), None);
+    }
+
+    #[test]
+    fn world_neighborhood() {
+        let (registry, _) = BlockRegistry::from_file("resources/registry.json").unwrap();
+        let mut world: World<PalettedChunk> = World::new();
+        let center = ChunkPosition::new(0, -1, 0);
+        world.insert_chunk(center, PalettedChunk::filled(Block::hard_create(1)));
+        world.insert_chunk(center.offset(0, 0, 1), PalettedChunk::filled(Block::hard_create(1)));
+
+        // Only the side towards the loaded neighbour is hidden.
+        let neighborhood = world.neighborhood(&center, MissingNeighbor::Air).unwrap();
+        let visible = neighborhood.visible_blocks(&registry);
+        assert!(visible.iter().all(|(position, _)| {
+            position.x() == 0 || position.y() == 0 || position.z() == 0
+                || position.x() == CHUNK_WIDTH - 1 || position.y() == CHUNK_HEIGHT - 1
+        }));
+
+        let neighborhood = world.neighborhood(&center, MissingNeighbor::Solid).unwrap();
+        assert!(neighborhood.visible_blocks(&registry).is_empty());
+        assert!(world.neighborhood(&ChunkPosition::new(5, 5, 5), MissingNeighbor::Solid).is_none());
+
+        // Neighbours past the edge of the world follow the policy for missing ones.
+        let edge = ChunkPosition::new(i32::MAX, 0, 0);
+        world.insert_chunk(edge, PalettedChunk::filled(Block::hard_create(1)));
+        assert!(world.neighborhood(&edge, MissingNeighbor::Solid).unwrap().visible_blocks(&registry).is_empty());
+        assert!(!world.neighborhood(&edge, MissingNeighbor::Air).unwrap().visible_blocks(&registry).is_empty());
+    }
+}